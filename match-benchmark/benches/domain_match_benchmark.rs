@@ -18,6 +18,14 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     // let regex_matcher = RegexMatcher::new(PROXY);
     let old_matcher = Domains::new(PROXY.iter());
     let new_matcher = NewDomains::new(PROXY.iter());
+    let compact_matcher = h2sr::Domains::from_strs(PROXY.iter().copied()).unwrap();
+    let (compact_len, compact_size) = compact_matcher.trie_size();
+    println!(
+        "Trie size of {} domains: New Matcher {} KiB, Compact Matcher {} KiB",
+        compact_len,
+        new_matcher.heap_size() / 1024,
+        compact_size / 1024
+    );
 
     let mut proxy_for_shuffle = PROXY.to_vec();
     let test_same: Vec<String> = proxy_for_shuffle
@@ -47,6 +55,11 @@ pub fn criterion_benchmark(c: &mut Criterion) {
                     new_matcher.mat(attempt.as_bytes()),
                     "diff: {attempt}"
                 );
+                assert_eq!(
+                    matched,
+                    compact_matcher.contain_host(attempt.as_bytes()),
+                    "diff: {attempt}"
+                );
                 if !matched {
                     return Some(attempt);
                 }
@@ -76,6 +89,13 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             }
         })
     });
+    c.bench_function("Compact Matcher - Same as pattern", |b| {
+        b.iter(|| {
+            for test_domain in test_same.iter() {
+                assert!(compact_matcher.contain_host(test_domain.as_bytes()))
+            }
+        })
+    });
     c.bench_function("Old Matcher - Same as pattern", |b| {
         b.iter(|| {
             for test_domain in test_same.iter() {
@@ -99,6 +119,14 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         })
     });
 
+    c.bench_function("Compact Matcher - With prefix", |b| {
+        b.iter(|| {
+            for test_domain in test_prefix.iter() {
+                assert!(compact_matcher.contain_host(test_domain.as_bytes()))
+            }
+        })
+    });
+
     c.bench_function("Ac Matcher - With diff prefix", |b| {
         b.iter(|| {
             for test_domain in test_prefix_diff.iter() {
@@ -113,6 +141,13 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             }
         })
     });
+    c.bench_function("Compact Matcher - With diff prefix", |b| {
+        b.iter(|| {
+            for test_domain in test_prefix_diff.iter() {
+                assert!(!compact_matcher.contain_host(test_domain.as_bytes()))
+            }
+        })
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
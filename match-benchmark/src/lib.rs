@@ -252,6 +252,10 @@ pub mod new_domains {
             self.host_trie.shrink_to_fit();
         }
 
+        pub fn heap_size(&self) -> usize {
+            self.host_trie.capacity() * std::mem::size_of::<usize>()
+        }
+
         pub fn mat(&self, uri: &[u8]) -> bool {
             let mut current = 0usize;
             for &b in uri.iter().rev() {
@@ -0,0 +1,1989 @@
+// Loading of `config.toml` into the env.
+//
+// Errors of the entries are collected instead of stopping at the first one, so that all of them
+// can be fixed at once.
+
+use crate::subscription::Subscription;
+use crate::{Clients, Credential, Env, Listener, Outbound, Profile};
+use h2sr::db::{self, Database, DatabaseBuilder};
+use h2sr::geosite::{Domain_Type, GeoSiteList};
+use h2sr::gfwlist::GfwList;
+use h2sr::ipgeo::GeoIPList;
+use h2sr::rule::{Action, BlockMode, Rule, Rules};
+use h2sr::{Domains, Ips, Origin, FULL_PREFIX, KEYWORD_PREFIX, REGEXP_PREFIX};
+use hyper::Uri;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use once_cell::unsync;
+use protobuf::Message;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// An error of the config, located by the key, like `rules[0].domains[2]`, and the value of it.
+pub struct ConfigError {
+    file: PathBuf,
+    // Empty if the error is of the whole file.
+    key: String,
+    value: Option<String>,
+    message: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if !self.key.is_empty() {
+            write!(f, ": `{}`", self.key)?;
+        }
+        if let Some(value) = &self.value {
+            write!(f, " = {:?}", value)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// Problems found while loading the config. The config can't be used if there is any error, while
+// the warnings are of the entries that are likely mistakes.
+#[derive(Default)]
+pub struct Diagnostics {
+    pub errors: Vec<ConfigError>,
+    pub warnings: Vec<ConfigError>,
+}
+
+// A value of the config merged from an included file.
+struct IncludedValue {
+    // Key of the value in the merged config, like `rules[3]`.
+    key: String,
+    file: PathBuf,
+    // Key of the value in the file, like `rules[0]`.
+    file_key: String,
+}
+
+// Where the values merged from the included files are from.
+#[derive(Default)]
+struct Included(Vec<IncludedValue>);
+
+impl Included {
+    // Record that the value of `file_key` in `file` is merged as `key`. `nested` are the values
+    // `file` merged from its own included files, the value or the ones in it may be from them.
+    fn add(&mut self, key: String, file: &Path, file_key: String, nested: &Included) {
+        for value in &nested.0 {
+            match key_within(&value.key, &file_key) {
+                Some(rest) if !rest.is_empty() => self.0.push(IncludedValue {
+                    key: format!("{}{}", key, rest),
+                    file: value.file.clone(),
+                    file_key: value.file_key.clone(),
+                }),
+                _ => {}
+            }
+        }
+        let (file, file_key) = nested
+            .locate(&file_key)
+            .map_or((file, file_key), |(file, key)| (file, key));
+        self.0.push(IncludedValue {
+            key,
+            file: file.to_path_buf(),
+            file_key,
+        });
+    }
+
+    // The file the value of `key` is from, and the key of it in the file. `None` if the value is
+    // of the config file itself.
+    fn locate(&self, key: &str) -> Option<(&Path, String)> {
+        self.0
+            .iter()
+            .filter_map(|value| Some((value, key_within(key, &value.key)?)))
+            .max_by_key(|(value, _)| value.key.len())
+            .map(|(value, rest)| (value.file.as_path(), format!("{}{}", value.file_key, rest)))
+    }
+
+    // Directory the relative paths in the value of `key` are resolved against, which is `dir` of
+    // the config file if the value is not included.
+    fn dir<'a>(&'a self, key: &str, dir: &'a Path) -> &'a Path {
+        match self.locate(key) {
+            Some((file, _)) => file.parent().unwrap_or_else(|| Path::new("")),
+            None => dir,
+        }
+    }
+}
+
+// The rest of `key` after `parent` if it's the key of `parent` or a value in it, like `[0].ips` of
+// `rules[0].ips` in `rules`.
+fn key_within<'a>(key: &'a str, parent: &str) -> Option<&'a str> {
+    key.strip_prefix(parent)
+        .filter(|rest| rest.is_empty() || rest.starts_with('.') || rest.starts_with('['))
+}
+
+// Collects the errors and warnings of a config file, which are located in the files included by
+// it if the values are from them.
+struct Errors<'a> {
+    file: &'a Path,
+    included: Included,
+    errors: RefCell<Vec<ConfigError>>,
+    warnings: RefCell<Vec<ConfigError>>,
+}
+
+impl<'a> Errors<'a> {
+    fn new(file: &'a Path) -> Errors<'a> {
+        Errors {
+            file,
+            included: Included::default(),
+            errors: RefCell::new(Vec::new()),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn error(&self, key: &str, value: Option<&str>, message: impl Display) -> ConfigError {
+        match self.included.locate(key) {
+            Some((file, key)) => self.error_in(file, &key, value, message),
+            None => self.error_in(self.file, key, value, message),
+        }
+    }
+
+    fn error_in(
+        &self,
+        file: &Path,
+        key: &str,
+        value: Option<&str>,
+        message: impl Display,
+    ) -> ConfigError {
+        ConfigError {
+            file: file.to_path_buf(),
+            key: key.to_string(),
+            value: value.map(str::to_string),
+            message: message.to_string(),
+        }
+    }
+
+    fn push(&self, key: &str, value: Option<&str>, message: impl Display) {
+        let error = self.error(key, value, message);
+        self.errors.borrow_mut().push(error);
+    }
+
+    // Push the error of an included file.
+    fn push_in(&self, file: &Path, key: &str, value: Option<&str>, message: impl Display) {
+        let error = self.error_in(file, key, value, message);
+        self.errors.borrow_mut().push(error);
+    }
+
+    fn warn(&self, key: &str, value: Option<&str>, message: impl Display) {
+        let warning = self.error(key, value, message);
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.errors.borrow().is_empty()
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ActionConfig {
+    Direct,
+    Proxy,
+    Block,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum BlockModeConfig {
+    Forbidden,
+    Reset,
+    Close,
+}
+
+impl From<BlockModeConfig> for BlockMode {
+    fn from(mode: BlockModeConfig) -> BlockMode {
+        match mode {
+            BlockModeConfig::Forbidden => BlockMode::Forbidden,
+            BlockModeConfig::Reset => BlockMode::Reset,
+            BlockModeConfig::Close => BlockMode::Close,
+        }
+    }
+}
+
+// A dat file loaded when it's used.
+type Dat<T> = unsync::Lazy<anyhow::Result<T>, Box<dyn FnOnce() -> anyhow::Result<T>>>;
+type GeoIp = Dat<GeoIPList>;
+type GeoSite = Dat<GeoSiteList>;
+
+// How the built domains and IP ranges are cached in the rule database.
+#[derive(Clone, Copy)]
+enum RuleDatabase<'a> {
+    Unused,
+    // Use the ones in the database, which is up to date.
+    Load(&'a Database),
+    // Add the built ones to the database.
+    Compile(&'a RefCell<DatabaseBuilder>),
+}
+
+// What the rules in the config refer to.
+struct RuleContext<'a> {
+    // Directory of the config file, the relative paths of the included values are resolved
+    // against the directories of their files instead.
+    dir: &'a Path,
+    subscriptions: &'a [Subscription],
+    geoip: &'a GeoIp,
+    geosite: &'a GeoSite,
+    // Paths of the dat files, which are parts of the fingerprints of the rules.
+    dat_paths: [&'a Path; 2],
+    // Files the rules are built from, like the list files.
+    sources: &'a RefCell<Vec<PathBuf>>,
+    // The `gfwlist:` and `gfwlist-exceptions:` entries of the rules, which `check_rules` checks.
+    gfwlists: &'a RefCell<Vec<GfwListEntry>>,
+    outbounds: &'a [Arc<Outbound>],
+    blockmode: BlockMode,
+    database: RuleDatabase<'a>,
+    errors: &'a Errors<'a>,
+}
+
+impl RuleContext<'_> {
+    fn create_rule(&self, key: &str, rule: RuleConfig) -> Option<Rule> {
+        let action = self.to_action(key, rule.action, rule.outbound.as_deref(), rule.blockmode);
+        let domains = rule
+            .domains
+            .map(|domains| self.create_domains(&format!("{}.domains", key), &domains));
+        let ips = rule
+            .ips
+            .map(|ips| self.create_ips(&format!("{}.ips", key), &ips));
+        if matches!(domains, Some(None)) || matches!(ips, Some(None)) {
+            return None;
+        }
+        Some(Rule {
+            domains: domains.flatten(),
+            ips: ips.flatten(),
+            action: action?,
+        })
+    }
+
+    // `rules` are appended to `prefix`, the rules translated from the legacy keys, which are
+    // paired with their keys. `name` is `None` for the top-level rules.
+    fn create_profile(
+        &self,
+        name: Option<String>,
+        mut prefix: Vec<(String, Rule)>,
+        rules: Vec<RuleConfig>,
+        default: ActionConfig,
+        defaultoutbound: Option<&str>,
+    ) -> Option<Profile> {
+        let key = match &name {
+            Some(name) => format!("profiles.{}.", name),
+            None => String::new(),
+        };
+        let mut failed = false;
+        for (index, rule) in rules.into_iter().enumerate() {
+            let key = format!("{}rules[{}]", key, index);
+            match self.create_rule(&key, rule) {
+                Some(rule) => prefix.push((key, rule)),
+                None => failed = true,
+            }
+        }
+        let default = self.to_action(&format!("{}default", key), default, defaultoutbound, None);
+        if failed {
+            return None;
+        }
+        self.check_rules(&prefix);
+        let (keys, rules) = prefix.into_iter().unzip();
+        Some(Profile {
+            name,
+            rules: Rules {
+                rules,
+                default: default?,
+            },
+            keys,
+        })
+    }
+
+    // Warn about the rules that never match or may match any host, the IP ranges that are both directly connected and
+    // blocked, which are decided by the order of the rules, and the GFWLists proxied or blocked
+    // without their exceptions connected directly before.
+    fn check_rules(&self, rules: &[(String, Rule)]) {
+        for (key, rule) in rules {
+            if rule.domains.is_none() && rule.ips.is_none() {
+                self.errors
+                    .warn(key, None, "rule without `domains` or `ips` never matches");
+            } else if rule.ips.as_ref().is_none_or(Ips::is_empty)
+                && rule.domains.as_ref().is_none_or(Domains::is_empty)
+            {
+                self.errors
+                    .warn(key, None, "rule is empty, it never matches");
+            }
+            // Like `regexp:.*`, which is likely to match any host.
+            if let Some(matched) = rule.domains.as_ref().and_then(|d| d.matched(b"")) {
+                let from = matched
+                    .origin
+                    .map_or(String::new(), |origin| format!(" from {}", origin));
+                self.errors.warn(
+                    key,
+                    None,
+                    format_args!(
+                        "`{}`{} matches the empty host, it may match any host",
+                        matched.entry, from
+                    ),
+                );
+            }
+        }
+        let ips = |action: fn(&Action) -> bool| {
+            rules
+                .iter()
+                .filter(move |(_, rule)| action(&rule.action))
+                .filter_map(|(key, rule)| Some((key, rule.ips.as_ref()?)))
+        };
+        for (direct_key, direct) in ips(|action| matches!(action, Action::Direct)) {
+            for (block_key, block) in ips(|action| matches!(action, Action::Block(_))) {
+                if let Some(overlap) = direct.intersect(block).iter().next() {
+                    self.errors.warn(
+                        direct_key,
+                        None,
+                        format_args!("overlaps blocked `{}` at {}", block_key, overlap),
+                    );
+                }
+            }
+        }
+        let gfwlists = self.gfwlists.borrow();
+        let gfwlists_of = |key: &str| {
+            let key = key.to_string();
+            gfwlists
+                .iter()
+                .filter(move |list| key_within(&list.key, &key).is_some())
+        };
+        for (index, (key, rule)) in rules.iter().enumerate() {
+            if matches!(rule.action, Action::Direct) {
+                continue;
+            }
+            for list in gfwlists_of(key).filter(|list| !list.exceptions) {
+                let excepted = rules[..index].iter().any(|(key, rule)| {
+                    matches!(rule.action, Action::Direct)
+                        && gfwlists_of(key)
+                            .any(|except| except.exceptions && except.source == list.source)
+                });
+                if !excepted {
+                    self.errors.warn(
+                        &list.key,
+                        Some(&list.entry),
+                        format_args!(
+                            "`@@` exceptions also match, add `{}{}` to a direct rule before",
+                            GFWLIST_EXCEPTIONS_PREFIX,
+                            &list.entry[GFWLIST_PREFIX.len()..]
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn to_action(
+        &self,
+        key: &str,
+        action: ActionConfig,
+        outbound: Option<&str>,
+        blockmode: Option<BlockModeConfig>,
+    ) -> Option<Action> {
+        action
+            .to_action(outbound, blockmode, self)
+            .map_err(|e| self.errors.push(key, None, e))
+            .ok()
+    }
+
+    // Path of the source of the entry, `None` if it's a subscription not downloaded yet, which is
+    // treated as empty.
+    fn source(&self, key: &str, entry: &str, path: &str) -> Result<Option<PathBuf>, String> {
+        let dir = self.errors.included.dir(key, self.dir);
+        let source = source_path(dir, self.subscriptions, path)?;
+        self.sources.borrow_mut().push(source.clone());
+        if is_downloaded(&source, path, key, entry, self.errors) {
+            Ok(Some(source))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Fingerprint of the rule in the database, which is changed with the entries or the dat files
+    // they may refer to.
+    fn fingerprint(&self, kind: &str, entries: &[String]) -> u64 {
+        let dat_paths = self
+            .dat_paths
+            .iter()
+            .map(|path| path.to_str().unwrap_or(""));
+        db::fingerprint(
+            std::iter::once(kind)
+                .chain(dat_paths)
+                .chain(entries.iter().map(String::as_str)),
+        )
+    }
+
+    // The domains in the database if they are up to date, otherwise they are built and added to
+    // the database being compiled.
+    fn create_domains(&self, key: &str, entries: &[String]) -> Option<Domains> {
+        for (index, entry) in entries.iter().enumerate() {
+            if let Some((path, exceptions)) = gfwlist_entry(entry) {
+                let key = format!("{}[{}]", key, index);
+                let dir = self.errors.included.dir(&key, self.dir);
+                // The errors of the path are reported when the domains are built.
+                if let Ok(source) = source_path(dir, self.subscriptions, path) {
+                    self.gfwlists.borrow_mut().push(GfwListEntry {
+                        key,
+                        entry: entry.clone(),
+                        source,
+                        exceptions,
+                    });
+                }
+            }
+        }
+        let fingerprint = self.fingerprint("domains", entries);
+        if let RuleDatabase::Load(database) = self.database {
+            match database.domains(key, fingerprint) {
+                Some(Ok(domains)) => return Some(domains),
+                Some(Err(e)) => self.errors.warn(key, None, e),
+                None => {}
+            }
+        }
+        let domains = self.build_domains(key, entries)?;
+        if let RuleDatabase::Compile(builder) = self.database {
+            builder.borrow_mut().add_domains(key, fingerprint, &domains);
+        }
+        Some(domains)
+    }
+
+    // Build the domains from the config entries. `gfwlist:<path>` entries are replaced by the
+    // domains of the GFWList file, `gfwlist-exceptions:<path>` ones by its exceptions, `file:<path>`
+    // ones by the domains of the list file, relative paths are resolved against the directory of
+    // the config, and `geosite:<code>[@<attribute>]` ones by the domains of geosite.dat.
+    fn build_domains(&self, key: &str, entries: &[String]) -> Option<Domains> {
+        let mut domains = Domains::default();
+        let mut failed = false;
+        for (index, entry) in entries.iter().enumerate() {
+            let mut error = |message: &dyn Display| {
+                self.errors
+                    .push(&format!("{}[{}]", key, index), Some(entry), message);
+                failed = true;
+            };
+            if let Some((source, exceptions)) = gfwlist_entry(entry) {
+                let path = match self.source(&format!("{}[{}]", key, index), entry, source) {
+                    Ok(Some(path)) => path,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error(&e);
+                        continue;
+                    }
+                };
+                match fs::read(&path) {
+                    Ok(content) => {
+                        let origin = Origin { source, line: None };
+                        let list = GfwList::parse(&content);
+                        let hosts = if exceptions { list.direct } else { list.proxy };
+                        for host in hosts {
+                            if let Err(e) = domains.add_entry_from(&host, origin) {
+                                error(&format_args!("`{}`: {}", host, e));
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => error(&format_args!(
+                        "unable to read gfwlist file `{}`: {}",
+                        path.display(),
+                        e
+                    )),
+                }
+            } else if let Some(geosite) = entry.strip_prefix(GEOSITE_PREFIX) {
+                let key = format!("{}[{}]", key, index);
+                match geosite_domains(self.geosite, geosite) {
+                    Ok(GeoSiteDomains { found: false, .. }) => self.errors.warn(
+                        &key,
+                        Some(entry),
+                        format_args!("geosite `{}` not found", geosite),
+                    ),
+                    Ok(GeoSiteDomains { domains: hosts, .. }) => {
+                        let origin = Origin {
+                            source: entry,
+                            line: None,
+                        };
+                        for host in hosts {
+                            if let Err(e) = domains.add_entry_from(&host, origin) {
+                                error(&format_args!("`{}`: {}", host, e));
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => error(&format_args!("{:#}", e)),
+                }
+            } else if let Some(path) = entry.strip_prefix(FILE_PREFIX) {
+                let lines = self
+                    .source(&format!("{}[{}]", key, index), entry, path)
+                    .and_then(|path| path.map_or(Ok(Vec::new()), |path| read_list(&path)));
+                match lines {
+                    Ok(lines) => {
+                        for (line, host) in lines {
+                            let origin = Origin {
+                                source: path,
+                                line: Some(line),
+                            };
+                            if let Err(e) = domains.add_entry_from(&host, origin) {
+                                error(&format_args!("line {}: `{}`: {}", line, host, e));
+                            }
+                        }
+                    }
+                    Err(e) => error(&e),
+                }
+            } else if let Err(e) = domains.add_entry(entry) {
+                error(&e);
+            }
+        }
+        if failed {
+            return None;
+        }
+        domains
+            .build()
+            .map_err(|e| self.errors.push(key, None, e))
+            .ok()?;
+        Some(domains)
+    }
+
+    // Like `create_domains`, the IP ranges in the database if they are up to date.
+    fn create_ips(&self, key: &str, entries: &[String]) -> Option<Ips> {
+        let fingerprint = self.fingerprint("ips", entries);
+        if let RuleDatabase::Load(database) = self.database {
+            match database.ips(key, fingerprint) {
+                Some(Ok(ips)) => return Some(ips),
+                Some(Err(e)) => self.errors.warn(key, None, e),
+                None => {}
+            }
+        }
+        let ips = self.build_ips(key, entries)?;
+        if let RuleDatabase::Compile(builder) = self.database {
+            builder.borrow_mut().add_ips(key, fingerprint, &ips);
+        }
+        Some(ips)
+    }
+
+    // `file:<path>` entries are replaced by the entries of the list file.
+    fn build_ips<'a>(&self, key: &str, entries: &'a [String]) -> Option<Ips> {
+        let mut ipnets = Vec::new();
+        let mut failed = false;
+        for (index, entry) in entries.iter().enumerate() {
+            let key = format!("{}[{}]", key, index);
+            // The IP ranges of the entry in the config are from the entry if it's `geo:<code>`,
+            // and the ones of a list file are from the line.
+            let mut add_ips = |ips: &str, origin: Option<Origin<'a>>| -> Result<(), String> {
+                if let Some(geo) = ips.strip_prefix(GEO_PREFIX) {
+                    let matched = geo_ipnets(self.geoip, geo).map_err(|e| format!("{:#}", e))?;
+                    if matched.is_empty() {
+                        self.errors.warn(
+                            &key,
+                            Some(entry),
+                            format_args!("geo `{}` not found", geo),
+                        );
+                    }
+                    let origin = origin.or(Some(Origin {
+                        source: entry,
+                        line: None,
+                    }));
+                    ipnets.extend(matched.into_iter().map(|ipnet| (ipnet, origin)));
+                } else {
+                    let ipnet = ips.parse::<IpNet>().map_err(|_| "illegal ip".to_string())?;
+                    ipnets.push((ipnet, origin))
+                }
+                Ok(())
+            };
+            let result = match entry.strip_prefix(FILE_PREFIX) {
+                Some(path) => self
+                    .source(&key, entry, path)
+                    .and_then(|source| source.map_or(Ok(Vec::new()), |source| read_list(&source)))
+                    .and_then(|lines| {
+                        lines.into_iter().try_for_each(|(line, ips)| {
+                            let origin = Origin {
+                                source: path,
+                                line: Some(line),
+                            };
+                            add_ips(&ips, Some(origin))
+                                .map_err(|e| format!("line {}: `{}`: {}", line, ips, e))
+                        })
+                    }),
+                None => add_ips(entry, None),
+            };
+            if let Err(e) = result {
+                self.errors.push(&key, Some(entry), e);
+                failed = true;
+            }
+        }
+        if failed {
+            return None;
+        }
+        Ips::from_entries(ipnets.into_iter())
+            .map_err(|e| self.errors.push(key, None, e))
+            .ok()
+    }
+}
+
+const DEFAULT_OUTBOUND: &str = "default";
+impl ActionConfig {
+    // `outbound` selects the upstream of `proxy`, the one of `socks5addr` if not set.
+    // `blockmode` overrides the global one for `block`.
+    fn to_action(
+        self,
+        outbound: Option<&str>,
+        blockmode: Option<BlockModeConfig>,
+        context: &RuleContext,
+    ) -> Result<Action, String> {
+        if outbound.is_some() && !matches!(self, ActionConfig::Proxy) {
+            return Err("outbound can only be set for proxy".to_string());
+        }
+        if blockmode.is_some() && !matches!(self, ActionConfig::Block) {
+            return Err("blockmode can only be set for block".to_string());
+        }
+        match self {
+            ActionConfig::Direct => Ok(Action::Direct),
+            ActionConfig::Block => Ok(Action::Block(
+                blockmode.map_or(context.blockmode, BlockMode::from),
+            )),
+            ActionConfig::Proxy => {
+                let name = outbound.unwrap_or(DEFAULT_OUTBOUND);
+                match context.outbounds.iter().position(|o| o.name == name) {
+                    Some(index) => Ok(Action::Proxy(index)),
+                    None if outbound.is_none() => {
+                        Err("`socks5addr` is required by proxy without outbound".to_string())
+                    }
+                    None => Err(format!("outbound `{}` not found", name)),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OutboundConfig {
+    addr: SocketAddr,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+// Username/password authentication (RFC 1929) requires both.
+fn to_credential(
+    username: Option<String>,
+    password: Option<String>,
+) -> Result<Option<Credential>, &'static str> {
+    match (username, password) {
+        (Some(username), Some(password)) => Ok(Some(Credential { username, password })),
+        (None, None) => Ok(None),
+        _ => Err("username and password should be set together"),
+    }
+}
+
+#[derive(Deserialize)]
+struct SubscriptionConfig {
+    url: String,
+    // Seconds between the downloads.
+    interval: Option<u64>,
+    // Outbound to download through, directly if not set.
+    outbound: Option<String>,
+}
+
+const DEFAULT_SUBSCRIPTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const SUBSCRIPTION_PREFIX: &str = "@";
+// The name is the file name of the cache.
+fn is_subscription_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+// The cache of the subscription if `path` is `@<name>`, otherwise `path` relative to `dir`.
+fn source_path(dir: &Path, subscriptions: &[Subscription], path: &str) -> Result<PathBuf, String> {
+    match path.strip_prefix(SUBSCRIPTION_PREFIX) {
+        Some(name) => subscriptions
+            .iter()
+            .find(|subscription| subscription.name == name)
+            .map(|subscription| subscription.cache.clone())
+            .ok_or_else(|| format!("subscription `{}` not found", name)),
+        None => Ok(dir.join(path)),
+    }
+}
+// Warn if the source is a subscription whose cache is missing.
+fn is_downloaded(source: &Path, path: &str, key: &str, entry: &str, errors: &Errors) -> bool {
+    match path.strip_prefix(SUBSCRIPTION_PREFIX) {
+        Some(name) if !source.exists() => {
+            errors.warn(
+                key,
+                Some(entry),
+                format_args!("subscription `{}` is not downloaded yet", name),
+            );
+            false
+        }
+        _ => true,
+    }
+}
+
+#[derive(Deserialize)]
+struct RuleConfig {
+    domains: Option<Vec<String>>,
+    ips: Option<Vec<String>>,
+    action: ActionConfig,
+    outbound: Option<String>,
+    blockmode: Option<BlockModeConfig>,
+}
+
+#[derive(Deserialize)]
+struct ListenerConfig {
+    addr: SocketAddr,
+    // Name of the profile, the top-level rules if not set.
+    profile: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProfileConfig {
+    #[serde(default = "Vec::new")]
+    rules: Vec<RuleConfig>,
+    default: Option<ActionConfig>,
+    defaultoutbound: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    listen: Option<SocketAddr>,
+    #[serde(default = "Vec::new")]
+    listeners: Vec<ListenerConfig>,
+    #[serde(default = "BTreeMap::new")]
+    profiles: BTreeMap<String, ProfileConfig>,
+    socks5listen: Option<SocketAddr>,
+    socks5addr: Option<SocketAddr>,
+    socks5username: Option<String>,
+    socks5password: Option<String>,
+    #[serde(default = "BTreeMap::new")]
+    outbounds: BTreeMap<String, OutboundConfig>,
+    #[serde(default = "BTreeMap::new")]
+    subscriptions: BTreeMap<String, SubscriptionConfig>,
+    // Paths of geoip.dat and geosite.dat, or `@<name>` of subscriptions.
+    geoip: Option<String>,
+    geosite: Option<String>,
+    // Legacy keys, translated into rules evaluated before `rules`.
+    proxydomains: Option<Vec<String>>,
+    proxyips: Option<Vec<String>>,
+    directdomains: Option<Vec<String>>,
+    directips: Option<Vec<String>>,
+    #[serde(default = "Vec::new")]
+    blockips: Vec<String>,
+    #[serde(default = "Vec::new")]
+    blockdomains: Vec<String>,
+    #[serde(default = "Vec::new")]
+    rules: Vec<RuleConfig>,
+    default: Option<ActionConfig>,
+    defaultoutbound: Option<String>,
+    blockmode: Option<BlockModeConfig>,
+}
+
+const ILLEGAL_CIDR: &str = "illegal CIDR in geoip.dat";
+fn cidr_to_ipnet(cider: &h2sr::ipgeo::CIDR) -> anyhow::Result<IpNet> {
+    let ip = cider.get_ip();
+    let prefix = cider.get_prefix() as u8;
+    if let Ok(ipv6_bytes) = <[u8; 16]>::try_from(ip) {
+        Ok(IpNet::V6(Ipv6Net::new(ipv6_bytes.into(), prefix)?))
+    } else if let Ok(ipv4_bytes) = <[u8; 4]>::try_from(ip) {
+        Ok(IpNet::V4(Ipv4Net::new(ipv4_bytes.into(), prefix)?))
+    } else {
+        Err(anyhow::anyhow!(ILLEGAL_CIDR))
+    }
+}
+const GEO_PREFIX: &str = "geo:";
+// IP ranges of the geo, empty if it's not found.
+fn geo_ipnets(geoip_list: &GeoIp, geo: &str) -> anyhow::Result<Vec<IpNet>> {
+    let geoip_list = geoip_list
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("{:#}", e))?;
+    geoip_list
+        .get_entry()
+        .iter()
+        .filter(|geoip| geoip.get_country_code().eq_ignore_ascii_case(geo))
+        .flat_map(|geoip| geoip.get_cidr().iter().map(cidr_to_ipnet))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| e.context(ILLEGAL_CIDR))
+}
+const GEOSITE_PREFIX: &str = "geosite:";
+const ATTRIBUTE_SEPARATOR: char = '@';
+struct GeoSiteDomains {
+    found: bool,
+    // Entries of the domains, in the form of `Domains::add_entry`.
+    domains: Vec<String>,
+}
+// Domains of `<code>[@<attribute>...]`, the ones with all the attributes if any.
+fn geosite_domains(geosite_list: &GeoSite, geosite: &str) -> anyhow::Result<GeoSiteDomains> {
+    let geosite_list = geosite_list
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("{:#}", e))?;
+    let mut attributes = geosite.split(ATTRIBUTE_SEPARATOR);
+    let code = attributes.next().unwrap_or_default();
+    let attributes: Vec<_> = attributes.collect();
+    let mut result = GeoSiteDomains {
+        found: false,
+        domains: Vec::new(),
+    };
+    for site in geosite_list.get_entry() {
+        if !site.get_country_code().eq_ignore_ascii_case(code) {
+            continue;
+        }
+        result.found = true;
+        for domain in site.get_domain() {
+            let selected = attributes.iter().all(|attribute| {
+                domain
+                    .get_attribute()
+                    .iter()
+                    .any(|a| a.get_key().eq_ignore_ascii_case(attribute))
+            });
+            if !selected {
+                continue;
+            }
+            match domain.get_field_type() {
+                Domain_Type::Domain => result.domains.push(domain.get_value().to_string()),
+                Domain_Type::Full => {
+                    result
+                        .domains
+                        .push(format!("{}{}", FULL_PREFIX, domain.get_value()))
+                }
+                Domain_Type::Plain => {
+                    result
+                        .domains
+                        .push(format!("{}{}", KEYWORD_PREFIX, domain.get_value()))
+                }
+                Domain_Type::Regex => {
+                    result
+                        .domains
+                        .push(format!("{}{}", REGEXP_PREFIX, domain.get_value()))
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+const GEOIP_FILE: &str = "geoip.dat";
+const GEOSITE_FILE: &str = "geosite.dat";
+const DATABASE_FILE: &str = "rules.db";
+const GFWLIST_PREFIX: &str = "gfwlist:";
+const GFWLIST_EXCEPTIONS_PREFIX: &str = "gfwlist-exceptions:";
+const FILE_PREFIX: &str = "file:";
+// Entries of a list file, one per line, paired with the line numbers. Empty lines and comments
+// starting with `#` are skipped.
+fn read_list(path: &Path) -> Result<Vec<(usize, String)>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read list file `{}`: {}", path.display(), e))?;
+    Ok(content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, entry)| (line, entry.to_string()))
+        .collect())
+}
+// The path of a `gfwlist:` or `gfwlist-exceptions:` entry, and whether it's the latter.
+fn gfwlist_entry(entry: &str) -> Option<(&str, bool)> {
+    match entry.strip_prefix(GFWLIST_PREFIX) {
+        Some(path) => Some((path, false)),
+        None => Some((entry.strip_prefix(GFWLIST_EXCEPTIONS_PREFIX)?, true)),
+    }
+}
+struct GfwListEntry {
+    key: String,
+    entry: String,
+    source: PathBuf,
+    exceptions: bool,
+}
+// Load the dat file, `name` is the default file name of it in the errors.
+fn load_dat<M: Message>(path: &Path, name: &str) -> anyhow::Result<M> {
+    use anyhow::Context;
+    let mut buf_reader = BufReader::new(
+        fs::File::open(path).with_context(|| format!("unable to open {} file", name))?,
+    );
+    let mut proto_in = protobuf::CodedInputStream::from_buffered_reader(&mut buf_reader);
+    M::parse_from(&mut proto_in).with_context(|| format!("error while parsing {}", name))
+}
+// `$HOME/.h2sr`, where the config file is by default.
+pub fn h2sr_dir() -> Option<PathBuf> {
+    let mut h2sr_dir: PathBuf = directories::UserDirs::new()?.home_dir().to_path_buf();
+    h2sr_dir.push(".h2sr");
+    Some(h2sr_dir)
+}
+
+// Where the config is loaded from, and what overrides it.
+pub struct Options {
+    pub config: PathBuf,
+    // Replace `geoip` and `geosite` of the config.
+    pub geoip: Option<PathBuf>,
+    pub geosite: Option<PathBuf>,
+    // Replaces `listen` of the config.
+    pub listen: Option<SocketAddr>,
+}
+
+impl Options {
+    // Relative paths in the config are resolved against the directory of it.
+    fn dir(&self) -> &Path {
+        self.config.parent().unwrap_or_else(|| Path::new(""))
+    }
+
+    // Where the subscriptions are downloaded to.
+    fn cache_dir(&self) -> PathBuf {
+        self.dir().join("cache")
+    }
+
+    // Where `compile` writes the rule database to.
+    pub fn database(&self) -> PathBuf {
+        self.cache_dir().join(DATABASE_FILE)
+    }
+}
+
+// Load the config, geoip.dat and geosite.dat, the latter ones are otherwise only loaded when
+// `geo:` and `geosite:` are used.
+pub fn check(options: &Options) -> (Option<Env>, Diagnostics) {
+    load(options, true, RuleDatabase::Unused)
+}
+
+// `None` if there is any error in the diagnostics. The rule database is used if it's up to date.
+pub fn load_env(options: &Options) -> (Option<Env>, Diagnostics) {
+    let database = open_database(&options.database());
+    let database = database
+        .as_ref()
+        .map_or(RuleDatabase::Unused, RuleDatabase::Load);
+    load(options, false, database)
+}
+
+// Build the rules like `check`, and write them to the rule database if there is no error.
+pub fn compile(options: &Options) -> (anyhow::Result<Option<Env>>, Diagnostics) {
+    use anyhow::Context;
+    let builder = RefCell::new(DatabaseBuilder::default());
+    let (env, diagnostics) = load(options, true, RuleDatabase::Compile(&builder));
+    let env = match env {
+        Some(env) => env,
+        None => return (Ok(None), diagnostics),
+    };
+    let mut builder = builder.into_inner();
+    for source in &env.sources {
+        builder.add_source(source);
+    }
+    let path = options.database();
+    let write = || -> anyhow::Result<()> {
+        fs::create_dir_all(options.cache_dir())?;
+        // The mapped database of a running h2sr is never modified.
+        let compiling = path.with_extension("compiling");
+        fs::write(&compiling, builder.to_bytes())?;
+        fs::rename(&compiling, &path)?;
+        Ok(())
+    };
+    let result = write()
+        .with_context(|| format!("unable to write `{}`", path.display()))
+        .map(|_| Some(env));
+    (result, diagnostics)
+}
+
+// The database if it's newer than its sources, which exist if and only if they existed when it
+// was compiled.
+fn open_database(path: &Path) -> Option<Database> {
+    let file = fs::File::open(path).ok()?;
+    let compiled = file
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    // SAFETY: `compile` replaces the database by renaming, instead of modifying the mapped file.
+    let map = unsafe { memmap2::Mmap::map(&file) }.ok()?;
+    let database = Database::parse(Arc::new(map)).ok()?;
+    let up_to_date = database.sources().iter().all(|(source, existed)| {
+        match fs::metadata(source).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => *existed && modified <= compiled,
+            Err(_) => !existed,
+        }
+    });
+    Some(database).filter(|_| up_to_date)
+}
+
+// The sources of the env are the config files read, the files its rules are built from, and the
+// sources of the rule database if it's used.
+fn load(options: &Options, check_dat: bool, database: RuleDatabase) -> (Option<Env>, Diagnostics) {
+    let mut errors = Errors::new(&options.config);
+    let mut sources = Vec::new();
+    let value = read_config(&options.config, &errors, &mut Vec::new(), &mut sources);
+    let config: Option<Config> = value.and_then(|(value, included)| {
+        errors.included = included;
+        value.try_into().map_err(|e| errors.push("", None, e)).ok()
+    });
+    let env = config
+        .and_then(|config| create_env(options, config, check_dat, database, &errors))
+        .filter(|_| errors.is_empty())
+        .map(|mut env| {
+            env.sources.extend(sources);
+            if let RuleDatabase::Load(database) = database {
+                let database_sources = database.sources().iter();
+                env.sources
+                    .extend(database_sources.map(|(source, _)| source.clone()));
+            }
+            env.sources.sort();
+            env.sources.dedup();
+            env
+        });
+    let diagnostics = Diagnostics {
+        errors: errors.errors.into_inner(),
+        warnings: errors.warnings.into_inner(),
+    };
+    (env, diagnostics)
+}
+
+const INCLUDE_KEY: &str = "include";
+// Read the config file merged with the files it includes, relative to its directory, and where the
+// merged values are from. `including` are the files including it, which it shouldn't include
+// again, and the files read are added to `read`.
+fn read_config(
+    path: &Path,
+    errors: &Errors,
+    including: &mut Vec<PathBuf>,
+    read: &mut Vec<PathBuf>,
+) -> Option<(toml::Value, Included)> {
+    read.push(path.to_path_buf());
+    let mut value: toml::Value = match fs::read(path) {
+        Ok(bytes) => toml::from_slice(&bytes)
+            .map_err(|e| errors.push_in(path, "", None, e))
+            .ok()?,
+        Err(e) => {
+            errors.push_in(
+                path,
+                "",
+                None,
+                format_args!("unable to read the file: {}", e),
+            );
+            return None;
+        }
+    };
+    let includes = match value.as_table_mut()?.remove(INCLUDE_KEY) {
+        Some(includes) => match includes.try_into::<Vec<String>>() {
+            Ok(includes) => includes,
+            Err(e) => {
+                errors.push_in(path, INCLUDE_KEY, None, e);
+                return None;
+            }
+        },
+        None => return Some((value, Included::default())),
+    };
+    including.push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    let mut merged = Included::default();
+    let mut failed = false;
+    for (index, include) in includes.iter().enumerate() {
+        let key = format!("{}[{}]", INCLUDE_KEY, index);
+        let include_path = path.parent().unwrap_or_else(|| Path::new("")).join(include);
+        let canonical = fs::canonicalize(&include_path).unwrap_or_else(|_| include_path.clone());
+        if including.contains(&canonical) {
+            errors.push_in(
+                path,
+                &key,
+                Some(include),
+                "the file is included recursively",
+            );
+            failed = true;
+            continue;
+        }
+        match read_config(&include_path, errors, including, read) {
+            Some((included, nested)) => {
+                let mut added = Vec::new();
+                merge(&mut value, included, "", &mut added);
+                for (key, file_key) in added {
+                    merged.add(key, &include_path, file_key, &nested);
+                }
+            }
+            None => failed = true,
+        }
+    }
+    including.pop();
+    if failed {
+        return None;
+    }
+    Some((value, merged))
+}
+
+// Merge the included config into `value`, which is of `key` in the config. Tables are merged,
+// arrays are concatenated with the entries of `value` first, so that its rules take precedence,
+// and other values of `value` are kept. The keys of the values added to `value` are pushed to
+// `added`, paired with their keys in the included config.
+fn merge(
+    value: &mut toml::Value,
+    included: toml::Value,
+    key: &str,
+    added: &mut Vec<(String, String)>,
+) {
+    match (value, included) {
+        (toml::Value::Table(table), toml::Value::Table(included)) => {
+            for (name, included) in included {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                match table.get_mut(&name) {
+                    Some(value) => merge(value, included, &key, added),
+                    None => {
+                        table.insert(name, included);
+                        added.push((key.clone(), key));
+                    }
+                }
+            }
+        }
+        (toml::Value::Array(array), toml::Value::Array(included)) => {
+            for (index, included) in included.into_iter().enumerate() {
+                added.push((
+                    format!("{}[{}]", key, array.len()),
+                    format!("{}[{}]", key, index),
+                ));
+                array.push(included);
+            }
+        }
+        _ => {}
+    }
+}
+
+// A dat file, which is the path of the options, or the entry of the config, or `file` next to the
+// config.
+struct DatFile<'a> {
+    path: Option<&'a PathBuf>,
+    entry: Option<String>,
+    key: &'a str,
+    file: &'a str,
+}
+
+impl DatFile<'_> {
+    // The path, which may not exist, and the file loaded when it's used. A subscription not
+    // downloaded yet is empty.
+    fn resolve<M: Message>(
+        self,
+        subscriptions: &[Subscription],
+        dir: &Path,
+        check: bool,
+        errors: &Errors,
+    ) -> (PathBuf, Dat<M>) {
+        let (path, downloaded) = match (self.path, &self.entry) {
+            (Some(path), _) => (path.clone(), true),
+            (None, Some(entry)) => match source_path(dir, subscriptions, entry) {
+                Ok(path) => {
+                    let downloaded = is_downloaded(&path, entry, self.key, entry, errors);
+                    (path, downloaded)
+                }
+                Err(e) => {
+                    errors.push(self.key, Some(entry), e);
+                    (dir.join(self.file), false)
+                }
+            },
+            (None, None) => (dir.join(self.file), true),
+        };
+        let name = self.file.to_string();
+        let dat: Dat<M> = if downloaded {
+            let path = path.clone();
+            unsync::Lazy::new(Box::new(move || load_dat(&path, &name)))
+        } else {
+            unsync::Lazy::new(Box::new(|| Ok(M::new())))
+        };
+        if check && path.exists() {
+            if let Err(e) = &*dat {
+                errors.push_in(&path, "", None, format_args!("{:#}", e));
+            }
+        }
+        (path, dat)
+    }
+}
+
+// `None` if there is any error, which is pushed to `errors`. The dat files are loaded even if they
+// are not used when `check_dat` is set.
+fn create_env(
+    options: &Options,
+    mut config: Config,
+    check_dat: bool,
+    database: RuleDatabase,
+    errors: &Errors,
+) -> Option<Env> {
+    if let Some(listen) = options.listen {
+        config.listen = Some(listen);
+    }
+
+    let mut outbounds = Vec::new();
+    if let Some(addr) = config.socks5addr {
+        let credential = to_credential(config.socks5username, config.socks5password)
+            .unwrap_or_else(|e| {
+                errors.push("socks5username", None, e);
+                None
+            });
+        outbounds.push(Arc::new(Outbound {
+            name: DEFAULT_OUTBOUND.to_string(),
+            addr,
+            credential,
+        }));
+    }
+    for (name, outbound) in config.outbounds {
+        let key = format!("outbounds.{}", name);
+        if name == DEFAULT_OUTBOUND && config.socks5addr.is_some() {
+            errors.push(&key, None, "conflicts with `socks5addr`");
+        }
+        let credential = to_credential(outbound.username, outbound.password).unwrap_or_else(|e| {
+            errors.push(&key, None, e);
+            None
+        });
+        outbounds.push(Arc::new(Outbound {
+            name,
+            addr: outbound.addr,
+            credential,
+        }));
+    }
+
+    let mut subscriptions = Vec::new();
+    for (name, subscription) in config.subscriptions {
+        let key = format!("subscriptions.{}", name);
+        if !is_subscription_name(&name) {
+            errors.push(
+                &key,
+                None,
+                "name should only contain letters, digits, `-` and `_`",
+            );
+            continue;
+        }
+        let url = match subscription.url.parse::<Uri>() {
+            Ok(url) if matches!(url.scheme_str(), Some("http") | Some("https")) => url,
+            Ok(_) => {
+                errors.push(
+                    &format!("{}.url", key),
+                    Some(&subscription.url),
+                    "should be http or https",
+                );
+                continue;
+            }
+            Err(e) => {
+                errors.push(&format!("{}.url", key), Some(&subscription.url), e);
+                continue;
+            }
+        };
+        let outbound = match subscription.outbound {
+            None => None,
+            Some(outbound) => match outbounds.iter().position(|o| o.name == outbound) {
+                Some(index) => Some(index),
+                None => {
+                    errors.push(
+                        &format!("{}.outbound", key),
+                        Some(&outbound),
+                        "outbound not found",
+                    );
+                    continue;
+                }
+            },
+        };
+        subscriptions.push(Subscription {
+            cache: options.cache_dir().join(&name),
+            name,
+            url,
+            interval: subscription
+                .interval
+                .map_or(DEFAULT_SUBSCRIPTION_INTERVAL, Duration::from_secs),
+            outbound,
+        });
+    }
+
+    let (geoip_path, geoip) = DatFile {
+        path: options.geoip.as_ref(),
+        entry: config.geoip,
+        key: "geoip",
+        file: GEOIP_FILE,
+    }
+    .resolve::<GeoIPList>(
+        &subscriptions,
+        errors.included.dir("geoip", options.dir()),
+        check_dat,
+        errors,
+    );
+    let (geosite_path, geosite) = DatFile {
+        path: options.geosite.as_ref(),
+        entry: config.geosite,
+        key: "geosite",
+        file: GEOSITE_FILE,
+    }
+    .resolve::<GeoSiteList>(
+        &subscriptions,
+        errors.included.dir("geosite", options.dir()),
+        check_dat,
+        errors,
+    );
+
+    let sources = RefCell::new(Vec::new());
+    let gfwlists = RefCell::new(Vec::new());
+    let context = RuleContext {
+        dir: options.dir(),
+        subscriptions: &subscriptions,
+        geoip: &geoip,
+        geosite: &geosite,
+        dat_paths: [&geoip_path, &geosite_path],
+        sources: &sources,
+        gfwlists: &gfwlists,
+        outbounds: &outbounds,
+        blockmode: config
+            .blockmode
+            .map_or(BlockMode::Forbidden, BlockMode::from),
+        database,
+        errors,
+    };
+    let mut rules = Vec::new();
+    let domain_rules = [
+        (
+            "blockdomains",
+            Some(config.blockdomains),
+            ActionConfig::Block,
+        ),
+        ("directdomains", config.directdomains, ActionConfig::Direct),
+        ("proxydomains", config.proxydomains, ActionConfig::Proxy),
+    ];
+    for (key, domains, action) in domain_rules.iter() {
+        if let Some(domains) = domains.as_ref().filter(|d| !d.is_empty()) {
+            let domains = context.create_domains(key, domains);
+            if let Some(action) = context.to_action(key, *action, None, None) {
+                rules.extend(
+                    domains.map(|domains| (key.to_string(), Rule::domains(domains, action))),
+                );
+            }
+        }
+    }
+    let default = match (&config.directips, &config.proxyips) {
+        (Some(_), None) => ActionConfig::Proxy,
+        _ => ActionConfig::Direct,
+    };
+    let ip_rules = [
+        ("blockips", Some(config.blockips), ActionConfig::Block),
+        ("directips", config.directips, ActionConfig::Direct),
+        ("proxyips", config.proxyips, ActionConfig::Proxy),
+    ];
+    for (key, ips, action) in ip_rules.iter() {
+        if let Some(ips) = ips.as_ref().filter(|i| !i.is_empty()) {
+            let ips = context.create_ips(key, ips);
+            if let Some(action) = context.to_action(key, *action, None, None) {
+                rules.extend(ips.map(|ips| (key.to_string(), Rule::ips(ips, action))));
+            }
+        }
+    }
+
+    let mut profiles = vec![context.create_profile(
+        None,
+        rules,
+        config.rules,
+        config.default.unwrap_or(default),
+        config.defaultoutbound.as_deref(),
+    )];
+    let mut profile_names = Vec::new();
+    for (name, profile) in config.profiles {
+        profiles.push(context.create_profile(
+            Some(name.clone()),
+            Vec::new(),
+            profile.rules,
+            profile.default.unwrap_or(ActionConfig::Direct),
+            profile.defaultoutbound.as_deref(),
+        ));
+        profile_names.push(name);
+    }
+
+    if config.listen.is_none() && config.listeners.is_empty() {
+        errors.push("", None, "either `listen` or `listeners` should be set");
+    }
+    let mut listeners: Vec<_> = config
+        .listen
+        .map(|addr| Listener { addr, profile: 0 })
+        .into_iter()
+        .collect();
+    for (index, listener) in config.listeners.into_iter().enumerate() {
+        let profile = match listener.profile {
+            None => 0,
+            Some(name) => match profile_names.iter().position(|n| *n == name) {
+                Some(position) => 1 + position,
+                None => {
+                    errors.push(
+                        &format!("listeners[{}].profile", index),
+                        Some(&name),
+                        "profile not found",
+                    );
+                    continue;
+                }
+            },
+        };
+        listeners.push(Listener {
+            addr: listener.addr,
+            profile,
+        });
+    }
+
+    Some(Env {
+        listeners,
+        socks5listen: config.socks5listen,
+        clients: Clients::new(&outbounds),
+        outbounds,
+        profiles: profiles.into_iter().collect::<Option<_>>()?,
+        subscriptions,
+        sources: sources
+            .into_inner()
+            .into_iter()
+            .chain(vec![geoip_path, geosite_path])
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_env, load, merge, Config, Errors, Options, RuleDatabase};
+    use crate::Env;
+    use h2sr::geosite::{Domain, Domain_Attribute, Domain_Type, GeoSite, GeoSiteList};
+    use h2sr::rule::{Action, BlockMode};
+    use protobuf::Message;
+    use std::fs;
+    use std::path::Path;
+
+    fn options() -> Options {
+        Options {
+            config: "config.toml".into(),
+            geoip: None,
+            geosite: None,
+            listen: None,
+        }
+    }
+
+    // Create the env of the config like `load`, with the errors and the warnings.
+    fn diagnose_with(options: &Options, config: &str) -> (Option<Env>, Vec<String>, Vec<String>) {
+        let config = toml::from_str(config).unwrap();
+        let errors = Errors::new(Path::new("config.toml"));
+        let env = create_env(options, config, false, RuleDatabase::Unused, &errors)
+            .filter(|_| errors.is_empty());
+        let messages = |errors: Vec<_>| errors.iter().map(ToString::to_string).collect();
+        (
+            env,
+            messages(errors.errors.into_inner()),
+            messages(errors.warnings.into_inner()),
+        )
+    }
+
+    fn diagnose(config: &str) -> (Option<Env>, Vec<String>, Vec<String>) {
+        diagnose_with(&options(), config)
+    }
+
+    #[test]
+    fn collect_errors() {
+        let (env, errors, _) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            blockips = ["1.2.3.4/33"]
+
+            [[rules]]
+            domains = ["ok.com", "bad!.com"]
+            action = "proxy"
+            "#,
+        );
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec![
+                "config.toml: `blockips[0]` = \"1.2.3.4/33\": illegal ip",
+                "config.toml: `rules[0]`: `socks5addr` is required by proxy without outbound",
+                "config.toml: `rules[0].domains[1]` = \"bad!.com\": unsupported character '!'",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_patterns() {
+        let (env, errors, _) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            directdomains = ["example.com", "keyword:", "regexp:"]
+            "#,
+        );
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec![
+                "config.toml: `directdomains[1]` = \"keyword:\": empty pattern",
+                "config.toml: `directdomains[2]` = \"regexp:\": empty pattern",
+            ]
+        );
+
+        let (env, errors, warnings) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            directdomains = ["example.com", "regexp:^.*$"]
+            blockdomains = ["ads.com", "*"]
+            "#,
+        );
+        assert!(env.is_some() && errors.is_empty(), "{:?}", errors);
+        assert_eq!(
+            warnings,
+            vec![
+                "config.toml: `blockdomains`: `*` matches the empty host, it may match any host",
+                "config.toml: `directdomains`: `regexp:^.*$` matches the empty host, it may match any host",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_outbound() {
+        let (env, errors, _) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            default = "proxy"
+            defaultoutbound = "us"
+
+            [outbounds.jp]
+            addr = "127.0.0.1:1087"
+
+            [[rules]]
+            domains = ["example.com"]
+            action = "proxy"
+            outbound = "jp"
+
+            [[rules]]
+            domains = ["example.net"]
+            action = "proxy"
+            outbound = "uk"
+            "#,
+        );
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec![
+                "config.toml: `rules[1]`: outbound `uk` not found",
+                "config.toml: `default`: outbound `us` not found",
+            ]
+        );
+    }
+
+    #[test]
+    fn credential_errors() {
+        let (env, errors, _) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            socks5addr = "127.0.0.1:1086"
+            socks5username = "user"
+
+            [outbounds.us]
+            addr = "127.0.0.1:1087"
+            password = "secret"
+
+            [outbounds.jp]
+            addr = "127.0.0.1:1088"
+            username = "user"
+            password = "secret"
+            "#,
+        );
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec![
+                "config.toml: `socks5username`: username and password should be set together",
+                "config.toml: `outbounds.us`: username and password should be set together",
+            ]
+        );
+    }
+
+    #[test]
+    fn block_modes() {
+        let (env, _, _) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            blockdomains = ["ads.com"]
+
+            [[rules]]
+            domains = ["tracker.com"]
+            action = "block"
+            "#,
+        );
+        let rules = &env.unwrap().profiles[0].rules;
+        assert_eq!(rules.rules[0].action, Action::Block(BlockMode::Forbidden));
+        assert_eq!(rules.rules[1].action, Action::Block(BlockMode::Forbidden));
+
+        let (env, _, _) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            blockmode = "reset"
+            blockdomains = ["ads.com"]
+
+            [[rules]]
+            domains = ["tracker.com"]
+            action = "block"
+
+            [[rules]]
+            domains = ["malware.com"]
+            action = "block"
+            blockmode = "close"
+            "#,
+        );
+        let rules = &env.unwrap().profiles[0].rules;
+        assert_eq!(rules.rules[0].action, Action::Block(BlockMode::Reset));
+        assert_eq!(rules.rules[1].action, Action::Block(BlockMode::Reset));
+        assert_eq!(rules.rules[2].action, Action::Block(BlockMode::Close));
+
+        let (env, errors, _) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+
+            [[rules]]
+            domains = ["example.com"]
+            action = "direct"
+            blockmode = "forbidden"
+            "#,
+        );
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec!["config.toml: `rules[0]`: blockmode can only be set for block"]
+        );
+        assert!(toml::from_str::<Config>(r#"blockmode = "drop""#).is_err());
+    }
+
+    #[test]
+    fn profiles() {
+        let (env, errors, _) = diagnose(
+            r#"
+            socks5addr = "127.0.0.1:1086"
+
+            [[listeners]]
+            addr = "127.0.0.1:8080"
+
+            [[listeners]]
+            addr = "127.0.0.1:8081"
+            profile = "work"
+
+            [outbounds.us]
+            addr = "127.0.0.1:1087"
+
+            [profiles.home]
+
+            [profiles.work]
+            default = "proxy"
+            defaultoutbound = "us"
+
+            [[profiles.work.rules]]
+            domains = ["intranet.com"]
+            action = "direct"
+            "#,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        let env = env.unwrap();
+        let names: Vec<_> = env.profiles.iter().map(|p| p.name.as_deref()).collect();
+        assert_eq!(names, vec![None, Some("home"), Some("work")]);
+        assert_eq!(env.profile("127.0.0.1:8080".parse().unwrap()), 0);
+        assert_eq!(env.profile("127.0.0.1:8081".parse().unwrap()), 2);
+        assert_eq!(env.profiles[0].rules.default, Action::Direct);
+        assert_eq!(env.profiles[1].rules.default, Action::Direct);
+        let work = &env.profiles[2];
+        assert_eq!(work.keys, vec!["profiles.work.rules[0]"]);
+        assert_eq!(work.rules.rules[0].action, Action::Direct);
+        assert_eq!(work.rules.default, Action::Proxy(1));
+    }
+
+    #[test]
+    fn profile_errors() {
+        let (env, errors, _) = diagnose(
+            r#"
+            [[listeners]]
+            addr = "127.0.0.1:8080"
+            profile = "missing"
+
+            [profiles.work]
+            default = "proxy"
+            defaultoutbound = "us"
+            "#,
+        );
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec![
+                "config.toml: `profiles.work.default`: outbound `us` not found",
+                "config.toml: `listeners[0].profile` = \"missing\": profile not found",
+            ]
+        );
+
+        let (env, errors, _) = diagnose("");
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec!["config.toml: either `listen` or `listeners` should be set"]
+        );
+    }
+
+    #[test]
+    fn warn_overlaps() {
+        let (env, _, warnings) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+
+            [[rules]]
+            ips = ["10.0.0.0/8"]
+            action = "direct"
+
+            [[rules]]
+            domains = []
+            action = "direct"
+
+            [[rules]]
+            ips = ["10.1.0.0/16", "192.168.0.0/16"]
+            action = "block"
+            "#,
+        );
+        assert!(env.is_some());
+        assert_eq!(
+            warnings,
+            vec![
+                "config.toml: `rules[1]`: rule is empty, it never matches",
+                "config.toml: `rules[0]`: overlaps blocked `rules[2]` at 10.1.0.0/16",
+            ]
+        );
+    }
+
+    #[test]
+    fn gfwlist_exceptions() {
+        let dir = std::env::temp_dir().join(format!("h2sr-gfwlist-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("gfw.txt"), "||blocked.com\n@@||ok.blocked.com\n").unwrap();
+        let options = Options {
+            config: dir.join("config.toml"),
+            ..options()
+        };
+        let (env, errors, warnings) = diagnose_with(
+            &options,
+            r#"
+            listen = "127.0.0.1:8080"
+            socks5addr = "127.0.0.1:1086"
+            proxydomains = ["gfwlist:gfw.txt"]
+
+            [[rules]]
+            domains = ["gfwlist-exceptions:gfw.txt"]
+            action = "direct"
+
+            [[rules]]
+            domains = ["gfwlist:gfw.txt"]
+            action = "block"
+            "#,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(
+            warnings,
+            vec![
+                "config.toml: `proxydomains[0]` = \"gfwlist:gfw.txt\": `@@` exceptions also match, \
+                 add `gfwlist-exceptions:gfw.txt` to a direct rule before"
+            ]
+        );
+        let rules = &env.unwrap().profiles[0].rules.rules;
+        let domains = |index: usize| rules[index].domains.as_ref().unwrap();
+        assert!(domains(1).contain_host(b"ok.blocked.com"));
+        assert!(!domains(1).contain_host(b"proxied.blocked.com"));
+        assert!(domains(2).contain_host(b"proxied.blocked.com"));
+    }
+
+    #[test]
+    fn subscription_sources() {
+        let (env, errors, warnings) = diagnose(
+            r#"
+            listen = "127.0.0.1:8080"
+            geoip = "@geoip"
+
+            [subscriptions.cn]
+            url = "https://example.com/cn.txt"
+
+            [subscriptions.geoip]
+            url = "ftp://example.com/geoip.dat"
+
+            [[rules]]
+            domains = ["file:@cn", "gfwlist:@gfwlist"]
+            action = "direct"
+            "#,
+        );
+        assert!(env.is_none());
+        assert_eq!(
+            errors,
+            vec![
+                "config.toml: `subscriptions.geoip.url` = \"ftp://example.com/geoip.dat\": should be http or https",
+                "config.toml: `geoip` = \"@geoip\": subscription `geoip` not found",
+                "config.toml: `rules[0].domains[1]` = \"gfwlist:@gfwlist\": subscription `gfwlist` not found",
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "config.toml: `rules[0].domains[0]` = \"file:@cn\": subscription `cn` is not downloaded yet",
+            ]
+        );
+    }
+
+    #[test]
+    fn geosite_entries() {
+        let mut site = GeoSite::new();
+        site.set_country_code("GOOGLE".to_string());
+        for (field_type, value, attribute) in [
+            (Domain_Type::Domain, "google.com", None),
+            (Domain_Type::Full, "ads.google.com", Some("ads")),
+            (Domain_Type::Plain, "googleads", Some("ads")),
+            (Domain_Type::Regex, "^ad[0-9]\\.google\\.com$", Some("ads")),
+        ] {
+            let mut domain = Domain::new();
+            domain.set_field_type(field_type);
+            domain.set_value(value.to_string());
+            if let Some(attribute) = attribute {
+                let mut a = Domain_Attribute::new();
+                a.set_key(attribute.to_string());
+                domain.mut_attribute().push(a);
+            }
+            site.mut_domain().push(domain);
+        }
+        let mut list = GeoSiteList::new();
+        list.mut_entry().push(site);
+        let path = std::env::temp_dir().join(format!("h2sr-geosite-{}.dat", std::process::id()));
+        fs::write(&path, list.write_to_bytes().unwrap()).unwrap();
+
+        let options = Options {
+            geosite: Some(path.clone()),
+            ..options()
+        };
+        let (env, _, warnings) = diagnose_with(
+            &options,
+            r#"
+            listen = "127.0.0.1:8080"
+
+            [[rules]]
+            domains = ["geosite:google@ads", "geosite:unknown"]
+            action = "block"
+            "#,
+        );
+        fs::remove_file(&path).unwrap();
+        let env = env.unwrap();
+        let domains = env.profiles[0].rules.rules[0].domains.as_ref().unwrap();
+        assert!(domains.contain_host(b"ads.google.com"));
+        assert!(!domains.contain_host(b"x.ads.google.com"));
+        assert!(!domains.contain_host(b"google.com"));
+        assert!(domains.contain_host(b"googleads.g.doubleclick.net"));
+        assert!(domains.contain_host(b"ad1.google.com"));
+        assert!(!domains.contain_host(b"ad1.google.com.hk"));
+        assert_eq!(
+            warnings,
+            vec![
+                "config.toml: `rules[0].domains[1]` = \"geosite:unknown\": geosite `unknown` not found",
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_included() {
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            default = "proxy"
+            proxydomains = ["mine.com"]
+            [outbounds.us]
+            addr = "127.0.0.1:1087"
+            "#,
+        )
+        .unwrap();
+        let included = toml::from_str(
+            r#"
+            default = "direct"
+            listen = "127.0.0.1:8080"
+            proxydomains = ["team.com"]
+            [outbounds.jp]
+            addr = "127.0.0.1:1088"
+            "#,
+        )
+        .unwrap();
+        let mut added = Vec::new();
+        merge(&mut value, included, "", &mut added);
+        let expected: toml::Value = toml::from_str(
+            r#"
+            default = "proxy"
+            listen = "127.0.0.1:8080"
+            proxydomains = ["mine.com", "team.com"]
+            [outbounds.us]
+            addr = "127.0.0.1:1087"
+            [outbounds.jp]
+            addr = "127.0.0.1:1088"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(value, expected);
+        let added: Vec<_> = added
+            .iter()
+            .map(|(key, file_key)| (key.as_str(), file_key.as_str()))
+            .collect();
+        assert_eq!(
+            added,
+            vec![
+                ("listen", "listen"),
+                ("outbounds.jp", "outbounds.jp"),
+                ("proxydomains[1]", "proxydomains[0]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn locate_included() {
+        let dir = std::env::temp_dir().join(format!("h2sr-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("team")).unwrap();
+        fs::write(
+            dir.join("config.toml"),
+            r#"
+            listen = "127.0.0.1:8080"
+            include = ["team/team.toml"]
+            proxydomains = ["mine.com"]
+
+            [[rules]]
+            domains = ["bad!.org"]
+            action = "direct"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("team/team.toml"),
+            r#"
+            socks5addr = "127.0.0.1:1086"
+            include = ["ips.toml"]
+            proxydomains = ["team.com", "bad!.com", "file:list.txt"]
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("team/ips.toml"),
+            r#"
+            [[rules]]
+            ips = ["10.0.0.0/8", "1.2.3.4/33"]
+            action = "direct"
+            "#,
+        )
+        .unwrap();
+        fs::write(dir.join("team/list.txt"), "listed.com\nbad!.net\n").unwrap();
+        let options = Options {
+            config: dir.join("config.toml"),
+            ..options()
+        };
+        let (env, diagnostics) = load(&options, false, RuleDatabase::Unused);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(env.is_none());
+        let errors: Vec<_> = diagnostics.errors.iter().map(|e| e.to_string()).collect();
+        let (config, team, ips) = (
+            dir.join("config.toml"),
+            dir.join("team/team.toml"),
+            dir.join("team/ips.toml"),
+        );
+        assert_eq!(
+            errors,
+            vec![
+                format!(
+                    "{}: `proxydomains[1]` = \"bad!.com\": unsupported character '!'",
+                    team.display()
+                ),
+                format!(
+                    "{}: `proxydomains[2]` = \"file:list.txt\": line 2: `bad!.net`: unsupported character '!'",
+                    team.display()
+                ),
+                format!(
+                    "{}: `rules[0].domains[0]` = \"bad!.org\": unsupported character '!'",
+                    config.display()
+                ),
+                format!(
+                    "{}: `rules[0].ips[1]` = \"1.2.3.4/33\": illegal ip",
+                    ips.display()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Binary database of the built rules, which is compiled ahead of time and then used in place, so
+//! the tries and the IP ranges are not rebuilt on every start.
+//!
+//! The file starts with [`MAGIC`] and [`VERSION`], followed by the source files of the rules, the
+//! index of the sections, and the sections. A section is the [`Domains`] or the [`Ips`] of a key,
+//! with the fingerprint of the entries it's built from. Integers are little-endian.
+//!
+//! The trie nodes, the IP networks and the origins are kept in flat arrays of fixed-size records,
+//! so a loaded section refers to its range of the database instead of being decoded.
+
+use crate::{Domains, Error, Ips, Result};
+use std::collections::HashMap;
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const MAGIC: &[u8; 6] = b"H2SRDB";
+/// Bumped on any change of the format, a database of another version is rebuilt.
+pub const VERSION: u16 = 3;
+
+const DOMAINS: u8 = 0;
+const IPS: u8 = 1;
+
+/// Bytes of the database, which may be a memory-mapped file.
+pub type Buffer = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+// Bytes owned by the structure, or a range of the database it's loaded from.
+pub(crate) enum Data {
+    Owned(Vec<u8>),
+    Shared(Buffer, Range<usize>),
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Data::Owned(bytes) => bytes,
+            Data::Shared(buffer, range) => &(**buffer).as_ref()[range.clone()],
+        }
+    }
+}
+
+impl Default for Data {
+    fn default() -> Self {
+        Data::Owned(Vec::new())
+    }
+}
+
+/// FNV-1a hash of the parts, which is stable across builds, unlike `DefaultHasher`.
+pub fn fingerprint<'a>(parts: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        // The length separates the parts, so `["ab", "c"]` differs from `["a", "bc"]`.
+        for b in (part.len() as u64)
+            .to_le_bytes()
+            .iter()
+            .chain(part.as_bytes())
+        {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+struct Section {
+    key: String,
+    kind: u8,
+    fingerprint: u64,
+    bytes: Vec<u8>,
+}
+
+/// Collects the built rules and the files they are built from.
+#[derive(Default)]
+pub struct DatabaseBuilder {
+    // Paths paired with whether they existed.
+    sources: Vec<(String, bool)>,
+    sections: Vec<Section>,
+}
+
+impl DatabaseBuilder {
+    /// Record a file the rules are built from, the database is outdated once it's modified,
+    /// created or removed.
+    pub fn add_source(&mut self, path: &Path) {
+        let source = (path.to_string_lossy().into_owned(), path.exists());
+        if !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+    }
+
+    pub fn add_domains(&mut self, key: &str, fingerprint: u64, domains: &Domains) {
+        let mut bytes = Vec::new();
+        domains.write_to(&mut bytes);
+        self.add_section(key, DOMAINS, fingerprint, bytes);
+    }
+
+    pub fn add_ips(&mut self, key: &str, fingerprint: u64, ips: &Ips) {
+        let mut bytes = Vec::new();
+        ips.write_to(&mut bytes);
+        self.add_section(key, IPS, fingerprint, bytes);
+    }
+
+    fn add_section(&mut self, key: &str, kind: u8, fingerprint: u64, bytes: Vec<u8>) {
+        self.sections.push(Section {
+            key: key.to_string(),
+            kind,
+            fingerprint,
+            bytes,
+        });
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        write_len(&mut header, self.sources.len());
+        for (path, exists) in &self.sources {
+            write_bytes(&mut header, path.as_bytes());
+            header.push(*exists as u8);
+        }
+        write_len(&mut header, self.sections.len());
+        // Offsets of the sections are relative to the end of the header.
+        let mut offset = 0;
+        for section in &self.sections {
+            write_bytes(&mut header, section.key.as_bytes());
+            header.push(section.kind);
+            header.extend_from_slice(&section.fingerprint.to_le_bytes());
+            write_len(&mut header, offset);
+            write_len(&mut header, section.bytes.len());
+            offset += section.bytes.len();
+        }
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 6 + header.len() + offset);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        write_len(&mut bytes, header.len());
+        bytes.append(&mut header);
+        for section in &self.sections {
+            bytes.extend_from_slice(&section.bytes);
+        }
+        bytes
+    }
+}
+
+/// A database loaded from the bytes, whose sections are used in place.
+pub struct Database {
+    buffer: Buffer,
+    sources: Vec<(PathBuf, bool)>,
+    // Kind, fingerprint and range of the sections by their keys.
+    sections: HashMap<String, (u8, u64, Range<usize>)>,
+}
+
+impl Database {
+    pub fn parse(buffer: Buffer) -> Result<Database> {
+        let bytes = (*buffer).as_ref();
+        let mut reader = Reader::new(bytes, 0..bytes.len());
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(illegal("not a rule database"));
+        }
+        let version = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+        if version != VERSION {
+            return Err(Error::IllegalDatabase(format!(
+                "version {} is not supported",
+                version
+            )));
+        }
+        let header = reader.bytes_range()?;
+        let body = header.end;
+        let mut reader = Reader::new(bytes, header);
+        let mut sources = Vec::new();
+        for _ in 0..reader.len()? {
+            let path = String::from_utf8_lossy(reader.bytes()?).into_owned();
+            sources.push((PathBuf::from(path), reader.u8()? != 0));
+        }
+        let mut sections = HashMap::new();
+        for _ in 0..reader.len()? {
+            let key = String::from_utf8_lossy(reader.bytes()?).into_owned();
+            let kind = reader.u8()?;
+            let fingerprint = reader.u64()?;
+            let start = body + reader.len()?;
+            let end = start + reader.len()?;
+            if end > bytes.len() {
+                return Err(illegal("section out of bounds"));
+            }
+            sections.insert(key, (kind, fingerprint, start..end));
+        }
+        Ok(Database {
+            buffer,
+            sources,
+            sections,
+        })
+    }
+
+    /// The files the rules are built from, paired with whether they existed.
+    pub fn sources(&self) -> &[(PathBuf, bool)] {
+        &self.sources
+    }
+
+    /// The domains of the key if they are built from the entries of the fingerprint.
+    pub fn domains(&self, key: &str, fingerprint: u64) -> Option<Result<Domains>> {
+        let range = self.section(key, DOMAINS, fingerprint)?;
+        let mut reader = Reader::new((*self.buffer).as_ref(), range);
+        Some(Domains::read_from(&mut reader, &self.buffer))
+    }
+
+    /// The IP ranges of the key if they are built from the entries of the fingerprint.
+    pub fn ips(&self, key: &str, fingerprint: u64) -> Option<Result<Ips>> {
+        let range = self.section(key, IPS, fingerprint)?;
+        let mut reader = Reader::new((*self.buffer).as_ref(), range);
+        Some(Ips::read_from(&mut reader, &self.buffer))
+    }
+
+    fn section(&self, key: &str, kind: u8, fingerprint: u64) -> Option<Range<usize>> {
+        match self.sections.get(key) {
+            Some((k, f, range)) if *k == kind && *f == fingerprint => Some(range.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn illegal(message: &str) -> Error {
+    Error::IllegalDatabase(message.to_string())
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+pub(crate) fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_len(bytes, data.len());
+    bytes.extend_from_slice(data);
+}
+
+// Reads a range of the database, any read out of the range is an error.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    end: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], range: Range<usize>) -> Reader<'a> {
+        Reader {
+            bytes,
+            position: range.start,
+            end: range.end,
+        }
+    }
+
+    /// The range of the next `len` bytes in the database.
+    pub fn range(&mut self, len: usize) -> Result<Range<usize>> {
+        let start = self.position;
+        match start.checked_add(len) {
+            Some(end) if end <= self.end => {
+                self.position = end;
+                Ok(start..end)
+            }
+            _ => Err(illegal("unexpected end of data")),
+        }
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let range = self.range(len)?;
+        Ok(&self.bytes[range])
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn len(&mut self) -> Result<usize> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    /// The range of the next length-prefixed bytes.
+    pub fn bytes_range(&mut self) -> Result<Range<usize>> {
+        let len = self.len()?;
+        self.range(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint, Buffer, Database, DatabaseBuilder};
+    use crate::{Domains, Ips, Origin};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn compile_and_load() {
+        let entries = [
+            "example.com",
+            "full:api.example.org",
+            "keyword:ads",
+            "regexp:^img[0-9]+\\.",
+            "cdn.example.com",
+        ];
+        let mut domains = Domains::default();
+        for (line, entry) in entries.iter().enumerate() {
+            let origin = Origin {
+                source: "list.txt",
+                line: Some(line + 1),
+            };
+            domains.add_entry_from(entry, origin).unwrap();
+        }
+        domains.build().unwrap();
+        let geo = Origin {
+            source: "geo:private",
+            line: None,
+        };
+        let ips = Ips::from_entries(
+            ["10.0.0.0/8", "10.1.0.0/16", "::1/128"]
+                .iter()
+                .map(|net| (net.parse().unwrap(), Some(geo))),
+        )
+        .unwrap();
+        let domains_fingerprint = fingerprint(entries.iter().copied());
+        let mut builder = DatabaseBuilder::default();
+        builder.add_source(Path::new("config.toml"));
+        builder.add_domains("rules[0].domains", domains_fingerprint, &domains);
+        builder.add_ips("rules[1].ips", 1, &ips);
+        let buffer: Buffer = Arc::new(builder.to_bytes());
+        let database = Database::parse(buffer.clone()).unwrap();
+
+        assert_eq!(database.sources().len(), 1);
+        assert!(database.domains("rules[0].domains", 0).is_none());
+        assert!(database
+            .ips("rules[0].domains", domains_fingerprint)
+            .is_none());
+        let domains = database
+            .domains("rules[0].domains", domains_fingerprint)
+            .unwrap()
+            .unwrap();
+        assert!(domains.contain_host(b"www.example.com"));
+        assert!(domains.contain_host(b"api.example.org"));
+        assert!(!domains.contain_host(b"www.api.example.org"));
+        assert!(domains.contain_host(b"ads.example.net"));
+        assert!(domains.contain_host(b"img12.example.net"));
+        let origin = |host: &str| domains.matched(host.as_bytes()).unwrap().origin;
+        assert_eq!(origin("api.example.org").unwrap().line, Some(2));
+        assert_eq!(origin("img12.example.net").unwrap().line, Some(4));
+        // The subdomain covered by the suffix is kept in the database.
+        let mut domains = domains;
+        assert!(domains.remove_host(b"example.com").unwrap());
+        assert!(!domains.contain_host(b"www.example.com"));
+        let matched = domains.matched(b"a.cdn.example.com").unwrap();
+        assert_eq!(matched.entry, "cdn.example.com");
+        assert_eq!(matched.origin.unwrap().line, Some(5));
+        let ips = database.ips("rules[1].ips", 1).unwrap().unwrap();
+        assert_eq!(
+            ips.matched_ipnet("10.1.2.3".parse().unwrap()),
+            Some("10.0.0.0/8".parse().unwrap())
+        );
+        assert!(ips.contain_ip("::1".parse().unwrap()));
+        let matched = ips.matched("::1".parse().unwrap()).unwrap();
+        assert_eq!(matched.origin, Some(geo));
+        assert!(!ips.contain_ip("11.0.0.1".parse().unwrap()));
+
+        // A truncated database is rejected instead of being read out of bounds.
+        let truncated: Buffer =
+            Arc::new((*buffer).as_ref()[..buffer.as_ref().as_ref().len() - 8].to_vec());
+        assert!(Database::parse(truncated).is_err());
+    }
+}
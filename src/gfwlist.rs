@@ -0,0 +1,227 @@
+//! Parser of the AutoProxy rule list format, which is used by GFWList.
+//!
+//! Only the rules that can be expressed as a domain suffix, or a glob pattern if the host contains
+//! `*`, are kept. Like a suffix, a glob pattern also matches the subdomains. Regular expression
+//! rules matching `^https?:\/\/` are kept as `regexp:` entries of their host part. Other regular
+//! expression rules, IP rules and rules whose host has an empty label or characters unsupported
+//! by [`Domains`] are ignored.
+
+use crate::{normalize_host, Domains, REGEXP_PREFIX};
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+#[derive(Default)]
+pub struct GfwList {
+    /// Domains that should be connected through the proxy.
+    pub proxy: Vec<String>,
+    /// Domains of the exception rules (`@@`), which should be connected directly.
+    pub direct: Vec<String>,
+}
+
+impl GfwList {
+    /// Parse an AutoProxy rule list, either in plain text or base64-encoded.
+    pub fn parse(content: &[u8]) -> GfwList {
+        let content = decode(content);
+        let mut list = GfwList::default();
+        for line in String::from_utf8_lossy(&content).lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+                continue;
+            }
+            let (line, domains) = match line.strip_prefix("@@") {
+                Some(line) => (line, &mut list.direct),
+                None => (line, &mut list.proxy),
+            };
+            if let Some(regex) = rule_regex(line) {
+                domains.push(format!("{}{}", REGEXP_PREFIX, regex));
+            } else if let Some(host) = rule_host(line) {
+                let host = host.to_ascii_lowercase();
+                if host.contains('*') {
+                    domains.push(format!("*.{}", host));
+                }
+                domains.push(host);
+            }
+        }
+        list
+    }
+}
+
+// GFWList is distributed base64-encoded. A plain list either starts with the `[AutoProxy x.x]`
+// header, a comment, or contains characters that are not in the base64 alphabet.
+fn decode(content: &[u8]) -> Cow<'_, [u8]> {
+    match content.iter().find(|b| !b.is_ascii_whitespace()) {
+        None | Some(b'[') | Some(b'!') => Cow::Borrowed(content),
+        Some(_) => {
+            let compact: Vec<u8> = content
+                .iter()
+                .copied()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect();
+            match base64::decode(&compact) {
+                Ok(decoded) => Cow::Owned(decoded),
+                Err(_) => Cow::Borrowed(content),
+            }
+        }
+    }
+}
+
+// Convert a regular expression rule of the URL into the one of the host, by cutting it at the
+// first `\/` after the scheme. `None` if it's not a regular expression rule, or it doesn't start
+// with the scheme, so the host part is unknown.
+fn rule_regex(rule: &str) -> Option<String> {
+    if rule.len() < 2 || !rule.starts_with('/') || !rule.ends_with('/') {
+        return None;
+    }
+    let regex = rule[1..rule.len() - 1].strip_prefix("^https?:\\/\\/")?;
+    // The path separator in a group or a class doesn't end the host.
+    let (mut depth, mut class, mut escaped) = (0, false, false);
+    let mut end = regex.len();
+    for (i, c) in regex.char_indices() {
+        if escaped {
+            escaped = false;
+            if c == '/' && depth == 0 && !class {
+                end = i - 1;
+                break;
+            }
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '[' => class = true,
+            ']' => class = false,
+            '(' if !class => depth += 1,
+            ')' if !class => depth -= 1,
+            _ => {}
+        }
+    }
+    let host = &regex[..end];
+    if host.is_empty() {
+        return None;
+    }
+    Some(format!("^{}$", host))
+}
+
+// Extract the domain suffix or the glob pattern of a rule, or `None` if the rule cannot be
+// expressed as one.
+fn rule_host(rule: &str) -> Option<&str> {
+    if rule.len() >= 2 && rule.starts_with('/') && rule.ends_with('/') {
+        return None; // Regular expression not matching the scheme
+    }
+    let mut host = rule;
+    if let Some(h) = host.strip_prefix("||") {
+        host = h;
+    } else if let Some(h) = host.strip_prefix('|') {
+        host = h;
+    }
+    for scheme in ["http://", "https://"].iter() {
+        if let Some(h) = host.strip_prefix(scheme) {
+            host = h;
+            break;
+        }
+    }
+    if let Some(path) = host.find('/') {
+        host = &host[..path];
+    }
+    if let Some(path) = host.find("%2F") {
+        host = &host[..path];
+    }
+    // A leading `*` matches the subdomains, which the suffix also does.
+    host = host.trim_start_matches('*');
+    if host.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    if let Some(port) = host.find(':') {
+        host = &host[..port];
+    }
+    let host = host.trim_start_matches('.');
+    if host.split('.').any(str::is_empty)
+        || host.parse::<IpAddr>().is_ok()
+        || host
+            .bytes()
+            .any(|b| b.is_ascii() && b != b'*' && Domains::check_char(b).is_err())
+        || (!host.is_ascii() && normalize_host(host.as_bytes()).is_err())
+    {
+        return None;
+    }
+    Some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GfwList;
+    use crate::Domains;
+
+    const LIST: &str = "[AutoProxy 0.2.9]
+! Checksum: abc
+! Comment line
+||google.com
+|http://example.org/path/to/page
+.twitter.com
+*.blogspot.com
+||cdn*.example.net
+http://foo.bar.com:8080/x
+@@||baidu.com
+@@|https://direct.example.net
+/^https?:\\/\\/[^\\/]+blogspot\\.(.*)/
+/^https?:\\/\\/([^\\/]+\\.)*google\\.(co\\.jp|de)\\/.*/
+/ad[0-9]+\\.js/
+1.2.3.4
+||under_score.com
+||trailing.dot.com.
+||例え.jp
+";
+
+    #[test]
+    fn parse_plain() {
+        let list = GfwList::parse(LIST.as_bytes());
+        assert_eq!(
+            list.proxy,
+            vec![
+                "google.com",
+                "example.org",
+                "twitter.com",
+                "blogspot.com",
+                "*.cdn*.example.net",
+                "cdn*.example.net",
+                "foo.bar.com",
+                r"regexp:^[^\/]+blogspot\.(.*)$",
+                r"regexp:^([^\/]+\.)*google\.(co\.jp|de)$",
+                "under_score.com",
+                "例え.jp",
+            ]
+        );
+        assert_eq!(list.direct, vec!["baidu.com", "direct.example.net"]);
+    }
+
+    #[test]
+    fn parse_base64() {
+        let encoded = base64::encode(LIST);
+        let wrapped = encoded
+            .as_bytes()
+            .chunks(64)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let list = GfwList::parse(wrapped.as_bytes());
+        assert_eq!(list.proxy.len(), 11);
+        assert_eq!(list.direct.len(), 2);
+    }
+
+    #[test]
+    fn domains_from_list() {
+        let list = GfwList::parse(LIST.as_bytes());
+        let proxy = Domains::from_strs(list.proxy.iter().map(|s| s.as_str())).unwrap();
+        assert!(proxy.contain_host(b"www.google.com"));
+        assert!(proxy.contain_host(b"a.blogspot.com"));
+        assert!(proxy.contain_host(b"cdn1.example.net"));
+        assert!(proxy.contain_host(b"img.cdn1.example.net"));
+        assert!(!proxy.contain_host(b"img.example.net"));
+        assert!(proxy.contain_host(b"foo.blogspot.de"));
+        assert!(proxy.contain_host(b"www.google.co.jp"));
+        assert!(proxy.contain_host(b"www.xn--r8jz45g.jp"));
+        assert!(!proxy.contain_host(b"www.google.co.uk"));
+        assert!(!proxy.contain_host(b"baidu.com"));
+        let direct = Domains::from_strs(list.direct.iter().map(|s| s.as_str())).unwrap();
+        assert!(direct.contain_host(b"www.baidu.com"));
+    }
+}
@@ -1,148 +1,516 @@
+pub mod db;
+pub mod gfwlist;
+// Generated by rust-protobuf, which predates some of the lints of the current toolchain.
+#[allow(renamed_and_removed_lints, unused_parens, mismatched_lifetime_syntaxes)]
+pub mod geosite;
+#[allow(renamed_and_removed_lints, unused_parens, mismatched_lifetime_syntaxes)]
 pub mod ipgeo;
+pub mod matcher;
+mod origin;
+mod ranges;
+pub mod rule;
+pub mod socks5;
+mod trie;
 
-use core::str;
+use db::{Buffer, Reader};
 use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use iprange::IpRange;
+use matcher::{Keywords, Regexes, Wildcards};
+use origin::{OriginIndex, Sources};
+use ranges::{Changes, Ranges};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::net::IpAddr;
+use trie::Trie;
 
-const NUM_ALPHABET: usize = 26;
-const NUM_DIGIT: usize = 10;
-const NUM_SPECIAL: usize = 2; // `.`, `-`
-const NUM_CHILDREN: usize = NUM_ALPHABET + NUM_DIGIT + NUM_SPECIAL;
-use std::{usize, fmt};
+use std::fmt;
 
-const MATCHED: usize = usize::MAX;
-const NOT_MATCHED: usize = usize::MAX / 2 + 1;
+pub use origin::{Matched, Origin};
 
-pub struct Domains {
-    // usize::MAX -> matched
-    // usize::MAX-1 -> Not matched
-    // other -> index of first child, should be <= self.0.len() - NUM_CHILDREN
-    // should not be empty
-    host_trie: Vec<usize>,
+/// Prefix of the entries matching the domain only, see [`Domains::add_entry`].
+pub const FULL_PREFIX: &str = "full:";
+/// Prefix of the entries matching the hosts containing the keyword.
+pub const KEYWORD_PREFIX: &str = "keyword:";
+/// Prefix of the entries matching the hosts by the regular expression.
+pub const REGEXP_PREFIX: &str = "regexp:";
+/// The number of the entries added or removed since the last build, over which
+/// [`Domains::apply`] builds.
+pub const MAX_PENDING: usize = 1024;
+
+/// Entries added and removed, which are applied to the built domains or IP ranges in place. The
+/// removed ones are applied first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diff<T> {
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
 }
 
-impl Default for Domains {
-    fn default() -> Self {
-        Domains {
-            host_trie: vec![NOT_MATCHED],
-        }
-    }
+#[derive(Default)]
+pub struct Domains {
+    host_trie: Trie,
+    // Domains added since the last build, which are merged into the trie by `build`.
+    pending: Vec<trie::Entry>,
+    // Flags removed since the last build from the domains of the trie, by their nodes.
+    removed: HashMap<usize, u8>,
+    // Domains left out of the trie for being covered by a suffix, which are added back once the
+    // suffix is removed.
+    covered: Vec<trie::Entry>,
+    // Checked after the trie, in the order.
+    keywords: Keywords,
+    wildcards: Wildcards,
+    regexes: Regexes,
+    // Origins of the patterns, by their indexes in the matchers.
+    keyword_origins: Vec<OriginIndex>,
+    wildcard_origins: Vec<OriginIndex>,
+    regex_origins: Vec<OriginIndex>,
+    sources: Sources,
 }
+
 impl Domains {
-    fn codec(ch: u8) -> Result<usize> {
-        if b'A' <= ch && ch <= b'Z' {
-            Ok((ch - b'A') as usize)
-        } else if b'a' <= ch && ch <= b'z' {
-            Ok((ch - b'a') as usize)
-        } else if b'0' <= ch && ch <= b'9' {
-            Ok((ch - b'0') as usize + NUM_ALPHABET)
-        } else if ch == b'.' {
-            Ok(NUM_ALPHABET + NUM_DIGIT)
-        } else if ch == b'-' {
-            Ok(NUM_ALPHABET + NUM_DIGIT + 1)
+    // Whether the character is allowed in the domains of the trie, which are `[A-Za-z0-9.-_]`.
+    fn check_char(ch: u8) -> Result<()> {
+        if ch.is_ascii_alphanumeric() || ch == b'.' || ch == b'-' || ch == b'_' {
+            Ok(())
         } else {
             Err(Error::UnexpectedCharacter(ch))
         }
     }
 
-    pub fn add_host(&mut self, suffix: &[u8]) -> Result<()> {
-        let mut current = 0;
-        for &b in suffix.iter().rev() {
-            let child = match self.host_trie[current] {
-                MATCHED => {
-                    if b == b'.' {
-                        return Ok(());
-                    }
-                    let child = self.host_trie.len();
-                    if child >= MATCHED - NOT_MATCHED {
-                        return Err(Error::TooManyDomains)
-                    }
-                    self.host_trie
-                        .extend(std::iter::repeat(NOT_MATCHED).take(NUM_CHILDREN));
-
-                    self.host_trie[current] = child + NOT_MATCHED;
-                    child
-                }
-                NOT_MATCHED => {
-                    let child = self.host_trie.len();
-                    if child >= NOT_MATCHED {
-                        return Err(Error::TooManyDomains)
-                    }
-                    self.host_trie
-                        .extend(std::iter::repeat(NOT_MATCHED).take(NUM_CHILDREN));
-
-                    self.host_trie[current] = child;
-                    child
+    // The reversed lowercase domain, in the form of the trie. A domain with an empty label, like
+    // `.example.com`, never matches, so it's illegal.
+    fn reversed(host: &[u8]) -> Result<Vec<u8>> {
+        let host = normalize_host(host)?;
+        if host.split(|&b| b == b'.').any(<[u8]>::is_empty) {
+            return Err(Error::IllegalDomain(
+                String::from_utf8_lossy(&host).into_owned(),
+            ));
+        }
+        let mut reversed = Vec::with_capacity(host.len());
+        for &b in host.iter().rev() {
+            Self::check_char(b)?;
+            reversed.push(b.to_ascii_lowercase());
+        }
+        Ok(reversed)
+    }
+
+    fn insert(&mut self, host: &[u8], flag: u8, origin: OriginIndex) -> Result<()> {
+        let reversed = Self::reversed(host)?;
+        self.pending.push((reversed, flag, origin));
+        Ok(())
+    }
+
+    // Remove the flag from the domain, wherever it's kept. Returns whether it's removed.
+    fn delete(&mut self, host: &[u8], flag: u8) -> Result<bool> {
+        let domain = Self::reversed(host)?;
+        let mut deleted = false;
+        for entries in [&mut self.pending, &mut self.covered].iter_mut() {
+            for (d, flags, _) in entries.iter_mut() {
+                if *d == domain && *flags & flag != 0 {
+                    *flags &= !flag;
+                    deleted = true;
                 }
-                child => {
-                    if child > NOT_MATCHED {
-                        child - NOT_MATCHED
-                    } else {
-                        child
-                    }
+            }
+            entries.retain(|(_, flags, _)| *flags != 0);
+        }
+        if let Some(index) = self.host_trie.get(&domain) {
+            let removed = self.removed.get(&index).copied().unwrap_or(0);
+            if self.host_trie.flags(index) & !removed & flag != 0 {
+                self.removed.insert(index, removed | flag);
+                deleted = true;
+                if flag == trie::SUFFIX {
+                    // The subdomains are matched by themselves now, until the next build, they are
+                    // matched one by one like the other domains added since.
+                    let (uncovered, covered) = std::mem::take(&mut self.covered)
+                        .into_iter()
+                        .partition(|(d, _, _)| {
+                            d.len() > domain.len()
+                                && d.starts_with(&domain)
+                                && d[domain.len()] == b'.'
+                        });
+                    self.covered = covered;
+                    self.pending.extend::<Vec<_>>(uncovered);
                 }
-            };
-            current = child + Self::codec(b).unwrap();
+            }
         }
-        if self.host_trie[current] == NOT_MATCHED {
-            self.host_trie[current] = MATCHED
+        Ok(deleted)
+    }
+
+    /// Match the domain and its subdomains.
+    pub fn add_host(&mut self, suffix: &[u8]) -> Result<()> {
+        self.insert(suffix, trie::SUFFIX, OriginIndex::default())
+    }
+
+    /// Match the domain only, not its subdomains.
+    pub fn add_full_host(&mut self, host: &[u8]) -> Result<()> {
+        self.insert(host, trie::FULL, OriginIndex::default())
+    }
+
+    /// Add an entry, which is `full:<host>`, `keyword:<keyword>`, `regexp:<regex>`, a glob
+    /// pattern containing `*` or `?`, or a domain matching itself and its subdomains.
+    pub fn add_entry(&mut self, entry: &str) -> Result<()> {
+        self.add(entry, OriginIndex::default())
+    }
+
+    /// Remove the domain added by [`Domains::add_host`], the subdomains added are kept. Returns
+    /// whether it's removed.
+    pub fn remove_host(&mut self, suffix: &[u8]) -> Result<bool> {
+        self.delete(suffix, trie::SUFFIX)
+    }
+
+    /// Remove the domain added by [`Domains::add_full_host`]. Returns whether it's removed.
+    pub fn remove_full_host(&mut self, host: &[u8]) -> Result<bool> {
+        self.delete(host, trie::FULL)
+    }
+
+    /// Remove an entry in the form of [`Domains::add_entry`]. Returns whether it's removed.
+    pub fn remove_entry(&mut self, entry: &str) -> Result<bool> {
+        if let Some(host) = entry.strip_prefix(FULL_PREFIX) {
+            self.remove_full_host(host.as_bytes())
+        } else if let Some(keyword) = entry.strip_prefix(KEYWORD_PREFIX) {
+            let index = self.keywords.remove(keyword);
+            Ok(index
+                .map(|index| self.keyword_origins.remove(index))
+                .is_some())
+        } else if let Some(regex) = entry.strip_prefix(REGEXP_PREFIX) {
+            let index = self.regexes.remove(regex);
+            Ok(index
+                .map(|index| self.regex_origins.remove(index))
+                .is_some())
+        } else if matcher::is_wildcard(entry) {
+            let index = self.wildcards.remove(entry);
+            Ok(index
+                .map(|index| self.wildcard_origins.remove(index))
+                .is_some())
         } else {
-            if self.host_trie[current] < NOT_MATCHED {
-                self.host_trie[current] += NOT_MATCHED
-            }
+            self.remove_host(entry.as_bytes())
+        }
+    }
+
+    /// Apply the diff of the entries in the form of [`Domains::add_entry`] to the domains, which
+    /// are matched right after, without rebuilding the trie. The changes are merged into the trie
+    /// by [`Domains::build`], which is also done once the entries added or removed since the last
+    /// build exceed [`MAX_PENDING`]. Nothing is applied if any entry is illegal.
+    pub fn apply(&mut self, diff: &Diff<String>) -> Result<()> {
+        let mut checked = Domains::default();
+        for entry in diff.removed.iter().chain(&diff.added) {
+            checked.add_entry(entry)?;
+        }
+        checked.build()?;
+        for entry in &diff.removed {
+            self.remove_entry(entry)?;
+        }
+        for entry in &diff.added {
+            self.add_entry(entry)?;
+        }
+        if self.changes() > MAX_PENDING {
+            self.build()?;
         }
         Ok(())
     }
 
-    pub fn build(&mut self) {
-        self.host_trie.shrink_to_fit();
+    // The number of the entries matched one by one, or skipped in the trie and the matchers, until
+    // the next build.
+    fn changes(&self) -> usize {
+        self.pending.len() + self.removed.len() + self.keywords.pending() + self.regexes.pending()
     }
 
-    pub fn contain_host(&self, uri: &[u8]) -> bool {
-        let mut current = 0usize;
-        for &b in uri.iter().rev() {
-            let child = self.host_trie[current];
-            if child > NOT_MATCHED && b == b'.' {
-                return true;
-            }
-            if child == NOT_MATCHED || child == MATCHED {
-                return false;
+    /// Add an entry like [`Domains::add_entry`], which is attributed to the origin by
+    /// [`Domains::matched`].
+    pub fn add_entry_from(&mut self, entry: &str, origin: Origin) -> Result<()> {
+        let origin = self.sources.index(Some(origin));
+        self.add(entry, origin)
+    }
+
+    fn add(&mut self, entry: &str, origin: OriginIndex) -> Result<()> {
+        if let Some(host) = entry.strip_prefix(FULL_PREFIX) {
+            self.insert(host.as_bytes(), trie::FULL, origin)
+        } else if let Some(keyword) = entry.strip_prefix(KEYWORD_PREFIX) {
+            self.keywords.add(keyword)?;
+            self.keyword_origins.push(origin);
+            Ok(())
+        } else if let Some(regex) = entry.strip_prefix(REGEXP_PREFIX) {
+            self.regexes.add(regex)?;
+            self.regex_origins.push(origin);
+            Ok(())
+        } else if matcher::is_wildcard(entry) {
+            self.wildcards.add(entry)?;
+            self.wildcard_origins.push(origin);
+            Ok(())
+        } else {
+            self.insert(entry.as_bytes(), trie::SUFFIX, origin)
+        }
+    }
+
+    /// Merge the entries added and removed since the last build into the trie and the matchers.
+    pub fn build(&mut self) -> Result<()> {
+        if !self.pending.is_empty() || !self.removed.is_empty() {
+            let removed = std::mem::take(&mut self.removed);
+            let mut entries: Vec<_> = self
+                .host_trie
+                .entries()
+                .into_iter()
+                .filter_map(|(index, (domain, flags, origin))| {
+                    let flags = flags & !removed.get(&index).copied().unwrap_or(0);
+                    Some((domain, flags, origin)).filter(|_| flags != 0)
+                })
+                .collect();
+            entries.append(&mut self.covered);
+            entries.append(&mut self.pending);
+            let (trie, covered) = Trie::build(entries)?;
+            self.host_trie = trie;
+            self.covered = covered;
+            self.pending = Vec::new();
+        }
+        self.keywords.build()?;
+        self.regexes.build()
+    }
+
+    /// Build the domains of the entries in the form of [`Domains::add_entry`].
+    pub fn from_strs<'a>(iter: impl Iterator<Item = &'a str>) -> Result<Domains> {
+        let mut domains = Domains::default();
+
+        for s in iter {
+            domains.add_entry(s)?
+        }
+        domains.build()?;
+
+        Ok(domains)
+    }
+
+    /// Whether no host can match, counting the entries added or removed since the last build.
+    pub fn is_empty(&self) -> bool {
+        // All the domains of the trie may be removed since the last build.
+        let trie_removed = || {
+            let removed = self
+                .removed
+                .iter()
+                .filter(|(&index, &removed)| self.host_trie.flags(index) & !removed == 0);
+            removed.count() == self.host_trie.len()
+        };
+        (self.host_trie.is_empty() || (!self.removed.is_empty() && trie_removed()))
+            && self.pending.is_empty()
+            && self.keywords.is_empty()
+            && self.wildcards.is_empty()
+            && self.regexes.is_empty()
+    }
+
+    /// The number of the domains in the trie, and the bytes it takes.
+    pub fn trie_size(&self) -> (usize, usize) {
+        (self.host_trie.len(), self.host_trie.size())
+    }
+
+    // Write the built domains in the format of the rule database.
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        debug_assert!(
+            self.pending.is_empty() && self.removed.is_empty(),
+            "domains are not built"
+        );
+        self.host_trie.write_to(bytes);
+        bytes.extend_from_slice(&(self.covered.len() as u32).to_le_bytes());
+        for (domain, flags, origin) in &self.covered {
+            db::write_bytes(bytes, domain);
+            bytes.push(*flags);
+            origin.encode(bytes);
+        }
+        for (patterns, origins) in [
+            (self.keywords.patterns(), &self.keyword_origins),
+            (self.wildcards.patterns(), &self.wildcard_origins),
+            (self.regexes.patterns(), &self.regex_origins),
+        ]
+        .iter()
+        {
+            bytes.extend_from_slice(&(patterns.len() as u32).to_le_bytes());
+            for (pattern, origin) in patterns.iter().zip(origins.iter()) {
+                db::write_bytes(bytes, pattern.as_bytes());
+                origin.encode(bytes);
             }
-            match Self::codec(b) {
-                Ok(n) => {
-                    current = n
-                        + (if child > NOT_MATCHED {
-                            child - NOT_MATCHED
-                        } else {
-                            child
-                        })
-                }
-                Err(_) => return false,
+        }
+        self.sources.write_to(bytes);
+    }
+
+    // The trie is used in place, while the other matchers are built again.
+    fn read_from(reader: &mut Reader, buffer: &Buffer) -> Result<Domains> {
+        let mut domains = Domains {
+            host_trie: Trie::read_from(reader, buffer)?,
+            ..Domains::default()
+        };
+        for _ in 0..reader.len()? {
+            let domain = reader.bytes()?.to_vec();
+            let flags = reader.u8()?;
+            let origin = OriginIndex::decode(reader.take(origin::ORIGIN_SIZE)?);
+            domains.covered.push((domain, flags, origin));
+        }
+        let mut read_patterns = |add: &mut dyn FnMut(&str, OriginIndex) -> Result<()>| {
+            for _ in 0..reader.len()? {
+                let pattern = std::str::from_utf8(reader.bytes()?)
+                    .map_err(|e| Error::IllegalDatabase(e.to_string()))?;
+                let origin = OriginIndex::decode(reader.take(origin::ORIGIN_SIZE)?);
+                add(pattern, origin)?;
             }
+            Ok(())
+        };
+        read_patterns(&mut |pattern, origin| {
+            domains.keywords.add(pattern)?;
+            domains.keyword_origins.push(origin);
+            Ok(())
+        })?;
+        read_patterns(&mut |pattern, origin| {
+            domains.wildcards.add(pattern)?;
+            domains.wildcard_origins.push(origin);
+            Ok(())
+        })?;
+        read_patterns(&mut |pattern, origin| {
+            domains.regexes.add(pattern)?;
+            domains.regex_origins.push(origin);
+            Ok(())
+        })?;
+        domains.sources = Sources::read_from(reader)?;
+        domains.build()?;
+        Ok(domains)
+    }
+
+    // The matched suffix, whether it's matched as a full domain, and the origin of the domain.
+    fn trie_match<'a>(&self, host: &'a [u8]) -> Option<(&'a [u8], bool, OriginIndex)> {
+        // The domains not built yet are matched one by one, and the shortest suffix is the one
+        // the trie would match.
+        let found = self
+            .trie_find(host)
+            .map(|(len, full, node)| (len, full, self.host_trie.origin(node)));
+        let (len, full, origin) = found
+            .into_iter()
+            .chain(self.pending.iter().filter_map(|(domain, flags, origin)| {
+                let (len, full) = trie::match_one(domain, *flags, host)?;
+                Some((len, full, *origin))
+            }))
+            .min()?;
+        Some((&host[(host.len() - len)..], full, origin))
+    }
+
+    // `Trie::find_node` skipping the domains removed since the last build.
+    fn trie_find(&self, host: &[u8]) -> Option<(usize, bool, usize)> {
+        if self.removed.is_empty() {
+            return self.host_trie.find_node(host);
         }
-        self.host_trie[current] > NOT_MATCHED
+        self.host_trie.find_node_where(host, |index, full| {
+            let flag = if full { trie::FULL } else { trie::SUFFIX };
+            self.removed
+                .get(&index)
+                .is_none_or(|removed| removed & flag == 0)
+        })
     }
+
+    // Whether any of the domains not built yet matches the host.
+    fn pending_match(&self, host: &[u8]) -> bool {
+        self.pending
+            .iter()
+            .any(|(domain, flags, _)| trie::match_one(domain, *flags, host).is_some())
+    }
+
+    /// The entry that [`Domains::contain_host`] matches the host by, in the form of
+    /// [`Domains::add_entry`].
+    pub fn matched_entry(&self, host: &[u8]) -> Option<String> {
+        self.matched(host).map(|matched| matched.entry)
+    }
+
+    /// The entry that [`Domains::contain_host`] matches the host by, in the form of
+    /// [`Domains::add_entry`], and its origin if it's added by [`Domains::add_entry_from`].
+    pub fn matched(&self, host: &[u8]) -> Option<Matched<'_, String>> {
+        let host = &*match_form(host);
+        let (entry, origin) = if let Some((suffix, full, origin)) = self.trie_match(host) {
+            let suffix = String::from_utf8_lossy(suffix);
+            let entry = if full {
+                format!("{}{}", FULL_PREFIX, suffix)
+            } else {
+                suffix.into_owned()
+            };
+            (entry, origin)
+        } else if let Some(index) = self.keywords.position(host) {
+            let keyword = &self.keywords.patterns()[index];
+            (
+                format!("{}{}", KEYWORD_PREFIX, keyword),
+                self.keyword_origins[index],
+            )
+        } else if let Some(index) = self.wildcards.position(host) {
+            let pattern = &self.wildcards.patterns()[index];
+            (pattern.clone(), self.wildcard_origins[index])
+        } else {
+            let index = self.regexes.position(host)?;
+            let regex = &self.regexes.patterns()[index];
+            (
+                format!("{}{}", REGEXP_PREFIX, regex),
+                self.regex_origins[index],
+            )
+        };
+        Some(Matched {
+            entry,
+            origin: self.sources.get(origin),
+        })
+    }
+
+    /// Whether the host, in Unicode or punycode, matches any entry.
+    pub fn contain_host(&self, uri: &[u8]) -> bool {
+        let uri = &*match_form(uri);
+        self.trie_find(uri).is_some()
+            || self.pending_match(uri)
+            || self.keywords.position(uri).is_some()
+            || self.wildcards.position(uri).is_some()
+            || self.regexes.position(uri).is_some()
+    }
+}
+
+/// Convert an internationalized domain name into its ASCII (punycode) form, which the trie stores,
+/// so the Unicode and the punycode forms match the same entries. ASCII hosts are kept as they are.
+pub fn normalize_host(host: &[u8]) -> Result<Cow<'_, [u8]>> {
+    if host.is_ascii() {
+        return Ok(Cow::Borrowed(host));
+    }
+    let unicode = std::str::from_utf8(host)
+        .map_err(|_| Error::IllegalDomain(String::from_utf8_lossy(host).into_owned()))?;
+    idna::domain_to_ascii(unicode)
+        .map(|ascii| Cow::Owned(ascii.into_bytes()))
+        .map_err(|_| Error::IllegalDomain(unicode.to_string()))
+}
+
+// The host to match, which is kept if it cannot be normalized, so it only fails to match the trie.
+fn match_form(host: &[u8]) -> Cow<'_, [u8]> {
+    normalize_host(host).unwrap_or(Cow::Borrowed(host))
 }
 
 #[derive(Default)]
 pub struct Ips {
-    ipv4: IpRange<Ipv4Net>,
-    ipv6: IpRange<Ipv6Net>,
+    // Merged ranges, which `contain_ip` looks up.
+    ipv4: Ranges<Ipv4Net>,
+    ipv6: Ranges<Ipv6Net>,
+    // The networks added, with their origins, which `matched` looks up. The ones contained by
+    // others are dropped.
+    ipv4_entries: Ranges<Ipv4Net>,
+    ipv6_entries: Ranges<Ipv6Net>,
+    sources: Sources,
+    // Changes since the ranges are built, which are merged into them by `build`.
+    ipv4_changes: Changes<Ipv4Net>,
+    ipv6_changes: Changes<Ipv6Net>,
 }
 
 #[derive(Debug)]
 pub enum Error {
     UnexpectedCharacter(u8),
     IllegalIpNet(String),
+    IllegalRegex(String),
+    IllegalDomain(String),
+    EmptyPattern,
+    IllegalDatabase(String),
     TooManyDomains,
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::UnexpectedCharacter(ch) => write!(f, "unknown character: '{:?}'", *ch as char),
+            Error::UnexpectedCharacter(ch) => write!(f, "unsupported character {:?}", *ch as char),
             Error::IllegalIpNet(str) => write!(f, "illegal ipnet: '{}'", str),
+            Error::IllegalRegex(e) => write!(f, "illegal regex: {}", e),
+            Error::IllegalDomain(domain) => write!(f, "illegal domain: '{}'", domain),
+            Error::EmptyPattern => write!(f, "empty pattern"),
+            Error::IllegalDatabase(e) => write!(f, "illegal rule database: {}", e),
             Error::TooManyDomains => write!(f, "too many domains"),
         }
     }
@@ -152,44 +520,591 @@ impl std::error::Error for Error {}
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-
 impl Ips {
-    fn add_ip(&mut self, ipnet: IpNet) -> Result<()> {
-        match ipnet {
-            IpNet::V4(ipnet) => {
-                self.ipv4.add(ipnet);
-            }
-            IpNet::V6(ipnet) => {
-                self.ipv6.add(ipnet);
+    // Merge the adjacent and overlapped ranges, which are then kept sorted for the lookups. The
+    // entries are the merged ranges if not given.
+    fn from_ranges(
+        ipv4: IpRange<Ipv4Net>,
+        ipv6: IpRange<Ipv6Net>,
+        entries: Option<Vec<(IpNet, OriginIndex)>>,
+        sources: Sources,
+    ) -> Ips {
+        let (ipv4_entries, ipv6_entries) = match entries {
+            Some(entries) => (
+                Ranges::with_origins(entries.iter().filter_map(|(net, origin)| match net {
+                    IpNet::V4(net) => Some((*net, *origin)),
+                    IpNet::V6(_) => None,
+                })),
+                Ranges::with_origins(entries.iter().filter_map(|(net, origin)| match net {
+                    IpNet::V4(_) => None,
+                    IpNet::V6(net) => Some((*net, *origin)),
+                })),
+            ),
+            None => (Ranges::new(ipv4.iter()), Ranges::new(ipv6.iter())),
+        };
+        Ips {
+            ipv4: Ranges::new(ipv4.iter()),
+            ipv6: Ranges::new(ipv6.iter()),
+            ipv4_entries,
+            ipv6_entries,
+            sources,
+            ..Ips::default()
+        }
+    }
+
+    /// Whether the ip is in any of the networks.
+    pub fn contain_ip(&self, ip: IpAddr) -> bool {
+        self.matched_ipnet(ip).is_some()
+    }
+
+    /// Build the ranges of the networks.
+    pub fn from_ipnets(iter: impl Iterator<Item = IpNet>) -> Result<Ips> {
+        Ips::from_entries(iter.map(|ipnet| (ipnet, None)))
+    }
+
+    /// Like [`Ips::from_ipnets`], with the origins of the networks, which [`Ips::matched`]
+    /// attributes the matches to.
+    pub fn from_entries<'a>(
+        iter: impl Iterator<Item = (IpNet, Option<Origin<'a>>)>,
+    ) -> Result<Ips> {
+        let mut ipv4 = IpRange::new();
+        let mut ipv6 = IpRange::new();
+        let mut sources = Sources::default();
+        let mut entries = Vec::new();
+
+        for (ipnet, origin) in iter {
+            let ipnet = ipnet.trunc();
+            entries.push((ipnet, sources.index(origin)));
+            match ipnet {
+                IpNet::V4(ipnet) => {
+                    ipv4.add(ipnet);
+                }
+                IpNet::V6(ipnet) => {
+                    ipv6.add(ipnet);
+                }
             }
         }
-        Ok(())
+        ipv4.simplify();
+        ipv6.simplify();
+
+        Ok(Ips::from_ranges(ipv4, ipv6, Some(entries), sources))
     }
 
-    fn build(&mut self) {
-        self.ipv4.simplify();
-        self.ipv6.simplify();
+    /// Add the network, which is matched right after, without rebuilding the ranges.
+    pub fn add_ipnet(&mut self, ipnet: IpNet) {
+        match ipnet.trunc() {
+            IpNet::V4(ipnet) => self.ipv4_changes.add(ipnet),
+            IpNet::V6(ipnet) => self.ipv6_changes.add(ipnet),
+        }
     }
 
-    pub fn contain_ip(&self, ip: IpAddr) -> bool {
+    /// Remove the addresses of the network, including the ones of the other networks
+    /// overlapping it, like `10.1.0.0/16` leaving the rest of `10.0.0.0/8`.
+    pub fn remove_ipnet(&mut self, ipnet: IpNet) {
+        match ipnet.trunc() {
+            IpNet::V4(ipnet) => self.ipv4_changes.remove(ipnet),
+            IpNet::V6(ipnet) => self.ipv6_changes.remove(ipnet),
+        }
+    }
+
+    /// Remove and then add the networks of the diff, see [`Ips::add_ipnet`] and
+    /// [`Ips::remove_ipnet`].
+    pub fn apply(&mut self, diff: &Diff<IpNet>) {
+        for ipnet in &diff.removed {
+            self.remove_ipnet(*ipnet);
+        }
+        for ipnet in &diff.added {
+            self.add_ipnet(*ipnet);
+        }
+    }
+
+    /// Merge the networks added and removed into the ranges, which [`Ips::iter`],
+    /// [`Ips::intersect`] and [`Ips::is_empty`] see after, and the lookups no longer check
+    /// separately.
+    pub fn build(&mut self) {
+        let ipv4 = std::mem::take(&mut self.ipv4_changes);
+        if !ipv4.is_empty() {
+            let (ranges, entries) = ipv4.apply(&self.ipv4, &self.ipv4_entries);
+            self.ipv4 = ranges;
+            self.ipv4_entries = entries;
+        }
+        let ipv6 = std::mem::take(&mut self.ipv6_changes);
+        if !ipv6.is_empty() {
+            let (ranges, entries) = ipv6.apply(&self.ipv6, &self.ipv6_entries);
+            self.ipv6 = ranges;
+            self.ipv6_entries = entries;
+        }
+    }
+
+    /// The network added that contains the ip, like `1.2.3.0/24` of the added `1.2.3.0/24`, and
+    /// its origin. A network contained by another one added is attributed to the latter.
+    pub fn matched(&self, ip: IpAddr) -> Option<Matched<'_, IpNet>> {
+        let (entry, origin) = match ip {
+            IpAddr::V4(ip) => match self.ipv4_changes.changed(&ip) {
+                Some(changed) => (IpNet::V4(changed?), OriginIndex::default()),
+                None => {
+                    let index = self.ipv4_entries.supernet_index(&ip)?;
+                    let (net, origin) = self.ipv4_entries.get_with_origin(index);
+                    (IpNet::V4(net), origin)
+                }
+            },
+            IpAddr::V6(ip) => match self.ipv6_changes.changed(&ip) {
+                Some(changed) => (IpNet::V6(changed?), OriginIndex::default()),
+                None => {
+                    let index = self.ipv6_entries.supernet_index(&ip)?;
+                    let (net, origin) = self.ipv6_entries.get_with_origin(index);
+                    (IpNet::V6(net), origin)
+                }
+            },
+        };
+        Some(Matched {
+            entry,
+            origin: self.sources.get(origin),
+        })
+    }
+
+    /// The range containing the ip, which is merged with the adjacent ones when built, or the
+    /// network added since containing it.
+    pub fn matched_ipnet(&self, ip: IpAddr) -> Option<IpNet> {
         match ip {
-            IpAddr::V4(ip) => self.ipv4.contains(&ip),
-            IpAddr::V6(ip) => self.ipv6.contains(&ip),
+            IpAddr::V4(ip) => match self.ipv4_changes.changed(&ip) {
+                Some(changed) => changed,
+                None => self.ipv4.supernet(&ip),
+            }
+            .map(IpNet::V4),
+            IpAddr::V6(ip) => match self.ipv6_changes.changed(&ip) {
+                Some(changed) => changed,
+                None => self.ipv6.supernet(&ip),
+            }
+            .map(IpNet::V6),
+        }
+    }
+
+    /// The addresses in both the built ranges and the ones of `other`.
+    pub fn intersect(&self, other: &Ips) -> Ips {
+        let ipv4: IpRange<Ipv4Net> = self.ipv4.iter().collect();
+        let ipv6: IpRange<Ipv6Net> = self.ipv6.iter().collect();
+        Ips::from_ranges(
+            ipv4.intersect(&other.ipv4.iter().collect()),
+            ipv6.intersect(&other.ipv6.iter().collect()),
+            None,
+            Sources::default(),
+        )
+    }
+
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        debug_assert!(
+            self.ipv4_changes.is_empty() && self.ipv6_changes.is_empty(),
+            "ips are not built"
+        );
+        self.ipv4.write_to(bytes);
+        self.ipv6.write_to(bytes);
+        self.ipv4_entries.write_to(bytes);
+        self.ipv6_entries.write_to(bytes);
+        self.sources.write_to(bytes);
+    }
+
+    fn read_from(reader: &mut Reader, buffer: &Buffer) -> Result<Ips> {
+        Ok(Ips {
+            ipv4: Ranges::read_from(reader, buffer)?,
+            ipv6: Ranges::read_from(reader, buffer)?,
+            ipv4_entries: Ranges::read_from(reader, buffer)?,
+            ipv6_entries: Ranges::read_from(reader, buffer)?,
+            sources: Sources::read_from(reader)?,
+            ..Ips::default()
+        })
+    }
+
+    /// Whether the built ranges are empty.
+    pub fn is_empty(&self) -> bool {
+        self.ipv4.is_empty() && self.ipv6.is_empty()
+    }
+
+    /// The networks covering the built ranges.
+    pub fn iter(&self) -> impl Iterator<Item = IpNet> + '_ {
+        self.ipv4
+            .iter()
+            .map(IpNet::V4)
+            .chain(self.ipv6.iter().map(IpNet::V6))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diff, Domains, Ips, Origin, MAX_PENDING};
+
+    #[test]
+    fn full_and_suffix() {
+        let mut domains = Domains::default();
+        domains.add_full_host(b"api.example.com").unwrap();
+        domains.add_full_host(b"example.org").unwrap();
+        domains.add_host(b"sub.example.org").unwrap();
+        domains.build().unwrap();
+
+        assert!(domains.contain_host(b"api.example.com"));
+        assert!(!domains.contain_host(b"x.api.example.com"));
+        assert!(!domains.contain_host(b"example.com"));
+        assert!(domains.contain_host(b"example.org"));
+        assert!(!domains.contain_host(b"www.example.org"));
+        assert!(domains.contain_host(b"sub.example.org"));
+        assert!(domains.contain_host(b"a.sub.example.org"));
+    }
+
+    #[test]
+    fn suffix_covers_full() {
+        let mut domains = Domains::default();
+        domains.add_host(b"example.com").unwrap();
+        domains.add_full_host(b"api.example.com").unwrap();
+        domains.add_full_host(b"example.com").unwrap();
+        domains.build().unwrap();
+
+        assert!(domains.contain_host(b"example.com"));
+        assert!(domains.contain_host(b"x.api.example.com"));
+        assert_eq!(
+            domains.matched_entry(b"api.example.com").as_deref(),
+            Some("example.com")
+        );
+
+        let mut domains = Domains::default();
+        domains.add_full_host(b"example.com").unwrap();
+        domains.add_host(b"example.com").unwrap();
+        assert!(domains.contain_host(b"www.example.com"));
+        assert_eq!(
+            domains.matched_entry(b"example.com").as_deref(),
+            Some("example.com")
+        );
+    }
+
+    #[test]
+    fn entries() {
+        let entries = [
+            "example.com",
+            "full:api.example.org",
+            "keyword:google",
+            "*.cdn.*.example.net",
+            r"regexp:^img[0-9]+\.",
+        ];
+        let domains = Domains::from_strs(entries.iter().copied()).unwrap();
+        let matched = |host: &str| domains.matched_entry(host.as_bytes());
+        assert_eq!(matched("www.example.com").as_deref(), Some("example.com"));
+        assert_eq!(
+            matched("api.example.org").as_deref(),
+            Some("full:api.example.org")
+        );
+        assert_eq!(matched("x.api.example.org"), None);
+        assert_eq!(matched("google.com").as_deref(), Some("keyword:google"));
+        assert_eq!(
+            matched("a.cdn.b.example.net").as_deref(),
+            Some("*.cdn.*.example.net")
+        );
+        assert_eq!(matched("cdn.example.net"), None);
+        assert_eq!(
+            matched("img1.example.net").as_deref(),
+            Some(r"regexp:^img[0-9]+\.")
+        );
+        assert!(!domains.is_empty());
+    }
+
+    #[test]
+    fn internationalized_and_underscore() {
+        let domains = Domains::from_strs(
+            ["例え.jp", "full:xn--fsq.example.com", "_dmarc.example.org"]
+                .iter()
+                .copied(),
+        )
+        .unwrap();
+
+        assert!(domains.contain_host("例え.jp".as_bytes()));
+        assert!(domains.contain_host(b"www.xn--r8jz45g.jp"));
+        assert!(domains.contain_host("例.example.com".as_bytes()));
+        assert!(!domains.contain_host("www.例.example.com".as_bytes()));
+        assert!(domains.contain_host(b"_dmarc.example.org"));
+        assert!(!domains.contain_host(b"dmarc.example.org"));
+        assert_eq!(
+            domains.matched_entry("www.例え.jp".as_bytes()).as_deref(),
+            Some("xn--r8jz45g.jp")
+        );
+    }
+
+    #[test]
+    fn empty_labels() {
+        for entry in [
+            "",
+            ".example.com",
+            "example..com",
+            "example.com.",
+            "full:",
+            "keyword:",
+            "regexp:",
+        ]
+        .iter()
+        .copied()
+        {
+            let mut domains = Domains::default();
+            assert!(domains.add_entry(entry).is_err(), "{:?}", entry);
         }
     }
 
-    // pub fn contain_ip(&self, ip: &IpAddr) -> bool {
+    #[test]
+    fn matched_origins() {
+        let list = |line| Origin {
+            source: "list.txt",
+            line: Some(line),
+        };
+        let mut domains = Domains::default();
+        domains.add_entry("example.com").unwrap();
+        domains.add_entry_from("example.org", list(3)).unwrap();
+        domains.add_entry_from("keyword:ads", list(4)).unwrap();
+        let geosite = Origin {
+            source: "geosite:google",
+            line: None,
+        };
+        domains.add_entry_from("full:google.com", geosite).unwrap();
+        let matched = |domains: &Domains, host: &str| {
+            let matched = domains.matched(host.as_bytes()).unwrap();
+            (
+                matched.entry,
+                matched.origin.map(|origin| origin.to_string()),
+            )
+        };
+        let assert_origins = |domains: &Domains| {
+            assert_eq!(
+                matched(domains, "www.example.com"),
+                ("example.com".to_string(), None)
+            );
+            assert_eq!(
+                matched(domains, "www.example.org"),
+                ("example.org".to_string(), Some("list.txt:3".to_string()))
+            );
+            assert_eq!(
+                matched(domains, "google.com"),
+                (
+                    "full:google.com".to_string(),
+                    Some("geosite:google".to_string())
+                )
+            );
+            assert_eq!(
+                matched(domains, "ads.example.net"),
+                ("keyword:ads".to_string(), Some("list.txt:4".to_string()))
+            );
+        };
+        // The domains not built yet are attributed the same as the built ones.
+        assert_origins(&domains);
+        domains.build().unwrap();
+        assert_origins(&domains);
+
+        let ips = Ips::from_entries(
+            [
+                ("10.0.0.0/8", Some(list(1))),
+                ("10.1.0.0/16", Some(list(2))),
+                ("11.0.0.0/8", None),
+                ("::1/128", Some(list(5))),
+            ]
+            .iter()
+            .map(|(net, origin)| (net.parse().unwrap(), *origin)),
+        )
+        .unwrap();
+        let matched = |ip: &str| {
+            let matched = ips.matched(ip.parse().unwrap()).unwrap();
+            (matched.entry.to_string(), matched.origin)
+        };
+        // `10.1.0.0/16` is contained by `10.0.0.0/8`, while both are merged with `11.0.0.0/8`.
+        assert_eq!(
+            matched("10.1.2.3"),
+            ("10.0.0.0/8".to_string(), Some(list(1)))
+        );
+        assert_eq!(matched("11.1.2.3"), ("11.0.0.0/8".to_string(), None));
+        assert_eq!(matched("::1"), ("::1/128".to_string(), Some(list(5))));
+        assert_eq!(
+            ips.matched_ipnet("11.1.2.3".parse().unwrap()),
+            Some("10.0.0.0/7".parse().unwrap())
+        );
+        assert!(ips.matched("12.0.0.1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn remove_overlapped_suffixes() {
+        let mut domains = Domains::from_strs(
+            [
+                "example.com",
+                "a.example.com",
+                "full:b.example.com",
+                "c.example.com",
+                "example.org",
+                "full:example.org",
+            ]
+            .iter()
+            .copied(),
+        )
+        .unwrap();
+        // The subdomain covered by the suffix is removed first, so it's not added back.
+        assert!(domains.remove_host(b"c.example.com").unwrap());
+        assert!(!domains.remove_host(b"c.example.com").unwrap());
+        assert!(domains.remove_host(b"Example.com").unwrap());
+        assert!(!domains.remove_host(b"example.com").unwrap());
+        assert!(!domains.remove_full_host(b"example.net").unwrap());
+        // The full domain is kept when the suffix of the same domain is removed.
+        assert!(domains.remove_entry("example.org").unwrap());
+
+        let assert_removed = |domains: &Domains| {
+            assert!(!domains.contain_host(b"example.com"));
+            assert!(!domains.contain_host(b"www.example.com"));
+            assert!(domains.contain_host(b"x.a.example.com"));
+            assert!(domains.contain_host(b"b.example.com"));
+            assert!(!domains.contain_host(b"x.b.example.com"));
+            assert!(!domains.contain_host(b"x.c.example.com"));
+            assert_eq!(
+                domains.matched_entry(b"example.org").as_deref(),
+                Some("full:example.org")
+            );
+            assert!(!domains.contain_host(b"www.example.org"));
+        };
+        assert_removed(&domains);
+        domains.build().unwrap();
+        assert_removed(&domains);
+        assert_eq!(domains.trie_size().0, 3);
 
-    // }
+        // Removing the shorter suffix added since the build uncovers the longer one.
+        domains.add_host(b"com").unwrap();
+        assert_eq!(
+            domains.matched_entry(b"x.a.example.com").as_deref(),
+            Some("com")
+        );
+        assert!(domains.remove_host(b"com").unwrap());
+        assert_eq!(
+            domains.matched_entry(b"x.a.example.com").as_deref(),
+            Some("a.example.com")
+        );
+    }
 
-    pub fn from_ipnets<'a>(iter: impl Iterator<Item = IpNet>) -> Result<Ips> {
-        let mut ips = Ips::default();
+    #[test]
+    fn add_and_remove_before_build() {
+        for (entry, host) in [
+            ("example.com", "www.example.com"),
+            ("full:example.com", "example.com"),
+            ("keyword:tracker", "a.tracker.net"),
+            ("*.cdn.example.com", "img.cdn.example.com"),
+            ("regexp:^ad[0-9]+\\.", "ad1.example.com"),
+        ]
+        .iter()
+        .copied()
+        {
+            let mut domains = Domains::from_strs(["example.org"].iter().copied()).unwrap();
+            // Added and removed entries are matched the same before and after the build.
+            domains.add_entry(entry).unwrap();
+            assert!(domains.contain_host(host.as_bytes()), "{}", entry);
+            assert_eq!(
+                domains.matched_entry(host.as_bytes()).as_deref(),
+                Some(entry)
+            );
+            domains.build().unwrap();
+            assert!(domains.contain_host(host.as_bytes()), "{}", entry);
+            assert!(domains.remove_entry(entry).unwrap(), "{}", entry);
+            assert!(!domains.contain_host(host.as_bytes()), "{}", entry);
+            domains.build().unwrap();
+            assert!(!domains.contain_host(host.as_bytes()), "{}", entry);
 
-        for ipnet in iter {
-            ips.add_ip(ipnet)?
+            domains.add_entry(entry).unwrap();
+            assert!(domains.remove_entry(entry).unwrap(), "{}", entry);
+            assert!(!domains.contain_host(host.as_bytes()), "{}", entry);
+            assert!(domains.contain_host(b"example.org"));
         }
+    }
+
+    #[test]
+    fn empty_after_removal() {
+        let mut domains = Domains::from_strs(
+            ["example.com", "full:example.com", "keyword:ads"]
+                .iter()
+                .copied(),
+        )
+        .unwrap();
+        assert!(domains.remove_entry("example.com").unwrap());
+        assert!(domains.remove_entry("keyword:ads").unwrap());
+        assert!(!domains.is_empty());
+        assert!(domains.remove_entry("full:example.com").unwrap());
+        assert!(domains.is_empty());
+        domains.build().unwrap();
+        assert!(domains.is_empty());
+    }
+
+    #[test]
+    fn apply_diff() {
+        let mut domains = Domains::from_strs(
+            ["example.com", "keyword:ads", "keyword:track"]
+                .iter()
+                .copied(),
+        )
+        .unwrap();
+        let diff = Diff {
+            added: vec![
+                "example.net".to_string(),
+                "regexp:^img[0-9]+\\.".to_string(),
+            ],
+            removed: vec!["example.com".to_string(), "keyword:ads".to_string()],
+        };
+        domains.apply(&diff).unwrap();
+        assert!(!domains.contain_host(b"www.example.com"));
+        assert!(domains.contain_host(b"www.example.net"));
+        assert!(!domains.contain_host(b"ads.example.org"));
+        assert!(domains.contain_host(b"track.example.org"));
+        assert!(domains.contain_host(b"img1.example.org"));
+
+        // Nothing is applied if any entry is illegal.
+        let illegal = Diff {
+            added: vec!["example.org".to_string(), "bad!host.com".to_string()],
+            removed: vec!["example.net".to_string()],
+        };
+        assert!(domains.apply(&illegal).is_err());
+        assert!(domains.contain_host(b"www.example.net"));
+        assert!(!domains.contain_host(b"www.example.org"));
+
+        // The trie is rebuilt once the removed domains exceed the limit.
+        let hosts: Vec<_> = (0..MAX_PENDING + 2)
+            .map(|i| format!("{}.example.com", i))
+            .collect();
+        let mut many = Domains::from_strs(hosts.iter().map(String::as_str)).unwrap();
+        many.apply(&Diff {
+            added: Vec::new(),
+            removed: hosts[1..].to_vec(),
+        })
+        .unwrap();
+        assert_eq!(many.trie_size().0, 1);
+        assert!(many.contain_host(b"0.example.com"));
+
+        let mut ips = Ips::from_ipnets(
+            ["10.0.0.0/8", "192.168.0.0/16"]
+                .iter()
+                .map(|net| net.parse().unwrap()),
+        )
+        .unwrap();
+        ips.apply(&Diff {
+            added: vec!["172.16.0.0/12".parse().unwrap()],
+            removed: vec![
+                "10.1.0.0/16".parse().unwrap(),
+                "192.168.0.0/16".parse().unwrap(),
+            ],
+        });
+        let matched = |ips: &Ips, ip: &str| {
+            ips.matched(ip.parse().unwrap())
+                .map(|matched| matched.entry.to_string())
+        };
+        let assert_applied = |ips: &Ips| {
+            assert_eq!(matched(ips, "10.1.0.1"), None);
+            assert_eq!(matched(ips, "192.168.1.1"), None);
+            assert_eq!(matched(ips, "172.20.0.1").as_deref(), Some("172.16.0.0/12"));
+        };
+        assert_applied(&ips);
+        assert_eq!(matched(&ips, "10.2.0.1").as_deref(), Some("10.0.0.0/8"));
         ips.build();
+        assert_applied(&ips);
+        // The entry removed in part is split into the rest.
+        assert_eq!(matched(&ips, "10.2.0.1").as_deref(), Some("10.2.0.0/15"));
+        assert_eq!(ips.iter().count(), 9);
 
-        Ok(ips)
+        // A network removed is added back.
+        ips.add_ipnet("10.1.2.0/24".parse().unwrap());
+        assert!(ips.contain_ip("10.1.2.3".parse().unwrap()));
+        assert!(!ips.contain_ip("10.1.3.3".parse().unwrap()));
     }
 }
@@ -1,70 +1,165 @@
-use futures_util::future::try_join;
-use h2sr::ipgeo::GeoIPList;
-use ipnet::{IpNet, Ipv4Net, Ipv6Net};
-use protobuf::Message;
+mod config;
+mod subscription;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{h2sr_dir, load_env, Diagnostics, Options};
+use futures_util::future::{join_all, try_join};
+use std::collections::HashMap;
 use std::io::Write;
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use std::{
-    convert::{Infallible, TryFrom},
-    fmt::Display,
-    io::BufReader,
-    net::SocketAddr,
+    fmt::{self, Display},
+    future::Future,
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
 };
-use std::{fs, net::IpAddr};
+use subscription::Subscription;
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use tokio_socks::tcp::Socks5Stream;
 
-use hyper::{
-    service::{make_service_fn, service_fn},
-    upgrade::Upgraded,
-};
-use hyper::{Body, Client, Method, Request, Response, Server};
+use hyper::http::uri::Authority;
+use hyper::server::conn::Http;
+use hyper::service::{service_fn, Service};
+use hyper::{Body, Client, Method, Request, Response, Uri};
 
 use anyhow::anyhow;
-use h2sr::{Domains, Ips};
-use once_cell::unsync;
-use serde::Deserialize;
-use std::path::Path;
-use tokio::net::{lookup_host, TcpStream};
+use h2sr::rule::{Action, BlockMode, Rules, Step};
+use h2sr::socks5::{self, Reply};
+use once_cell::sync::OnceCell;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+use tokio::sync::mpsc;
 
 type HttpClient = Client<hyper::client::HttpConnector>;
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum LogLevel {
+    Error,
+    Warning,
+    // The routing and the traffic of the connections.
+    Info,
+}
+
+impl LogLevel {
+    // Whether the logs of `level` are printed at this level.
+    fn shows(self, level: LogLevel) -> bool {
+        level <= self
+    }
+}
+
+static LOG_LEVEL: OnceCell<LogLevel> = OnceCell::new();
+
+fn log_enabled(level: LogLevel) -> bool {
+    LOG_LEVEL.get().unwrap_or(&LogLevel::Info).shows(level)
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if log_enabled(LogLevel::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Connector of the HTTP client, which connects through a SOCKS5 outbound. The TLS of HTTPS is up
+// to the client, like the one downloading the subscriptions.
+#[derive(Clone)]
+struct Socks5Connector(Arc<Outbound>);
+
+impl Service<Uri> for Socks5Connector {
+    type Response = TcpStream;
+    type Error = anyhow::Error;
+    type Future = Pin<Box<dyn Future<Output = anyhow::Result<TcpStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<anyhow::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let outbound = self.0.clone();
+        Box::pin(async move {
+            let default_port = match uri.scheme() {
+                Some(scheme) if *scheme == http::uri::Scheme::HTTP => 80,
+                Some(scheme) if *scheme == http::uri::Scheme::HTTPS => 443,
+                _ => return Err(anyhow!("scheme of '{}' is not http", uri)),
+            };
+            let auth = uri
+                .authority()
+                .ok_or_else(|| anyhow!("request host is illegal: '{}'", uri))?;
+            let target = format!(
+                "{}:{}",
+                auth.host(),
+                auth.port_u16().unwrap_or(default_port)
+            );
+            Ok(outbound.connect(&target).await?.into_inner())
+        })
+    }
+}
+
+struct Clients {
+    direct: HttpClient,
+    // Indexed by `Action::Proxy`.
+    proxies: Vec<Client<Socks5Connector>>,
+}
+
+impl Clients {
+    fn new(outbounds: &[Arc<Outbound>]) -> Clients {
+        Clients {
+            direct: HttpClient::new(),
+            proxies: outbounds
+                .iter()
+                .map(|outbound| Client::builder().build(Socks5Connector(outbound.clone())))
+                .collect(),
+        }
+    }
+}
+
 enum Connection {
     Direct,
-    Socks5,
+    Socks5(Arc<Outbound>),
 }
 
 impl Connection {
-    // Create a TCP connection to host:port, build a tunnel between the connection and
-    // the upgraded connection
-    async fn tunnel(
-        self,
-        auth: &str,
-        upgraded: Upgraded,
-        socks5addr: SocketAddr,
-    ) -> anyhow::Result<()> {
+    fn new(env: &Env, outbound: Option<usize>) -> Connection {
+        match outbound {
+            None => Connection::Direct,
+            Some(outbound) => Connection::Socks5(env.outbounds[outbound].clone()),
+        }
+    }
+
+    // Create a TCP connection to host:port
+    async fn open(&self, auth: &str) -> anyhow::Result<TcpStream> {
         match self {
             Connection::Direct => {
-                println!("DIRECT: {}", auth);
-                Ok(Self::tunnel_stream(auth, upgraded, &mut TcpStream::connect(auth).await?).await)
+                log_info!("DIRECT: {}", auth);
+                Ok(TcpStream::connect(auth).await?)
             }
-            Connection::Socks5 => {
-                println!("PROXY: {}", auth);
-                Ok(Self::tunnel_stream(
-                    auth,
-                    upgraded,
-                    &mut *Socks5Stream::connect(socks5addr, auth).await?,
-                )
-                .await)
+            Connection::Socks5(outbound) => {
+                log_info!("PROXY({}): {}", outbound.name, auth);
+                Ok(outbound.connect(auth).await?.into_inner())
             }
         }
     }
 
-    async fn tunnel_stream(auth: &str, upgraded: Upgraded, server: &mut TcpStream) {
+    // Create a TCP connection to host:port, build a tunnel between the connection and
+    // the client connection
+    async fn tunnel<C: AsyncRead + AsyncWrite>(self, auth: &str, client: C) -> anyhow::Result<()> {
+        Self::tunnel_stream(auth, client, &mut self.open(auth).await?).await;
+        Ok(())
+    }
+
+    async fn tunnel_stream<C: AsyncRead + AsyncWrite>(
+        auth: &str,
+        client: C,
+        server: &mut TcpStream,
+    ) {
         // Proxying data
         let amounts = {
             let (mut server_rd, mut server_wr) = server.split();
-            let (mut client_rd, mut client_wr) = tokio::io::split(upgraded);
+            let (mut client_rd, mut client_wr) = tokio::io::split(client);
 
             let client_to_server = tokio::io::copy(&mut client_rd, &mut server_wr);
             let server_to_client = tokio::io::copy(&mut server_rd, &mut client_wr);
@@ -75,9 +170,11 @@ impl Connection {
         // Print message when done
         match amounts {
             Ok((from_client, from_server)) => {
-                println!(
+                log_info!(
                     "[{}] client wrote {} bytes and received {} bytes",
-                    auth, from_client, from_server
+                    auth,
+                    from_client,
+                    from_server
                 );
             }
             Err(e) => {
@@ -87,246 +184,579 @@ impl Connection {
     }
 }
 
-const ERROR_WHILE_LOGGING: &'static str = "error while logging";
+const ERROR_WHILE_LOGGING: &str = "error while logging";
 fn log_error(auth: &str, log: impl Display) -> std::io::Result<()> {
+    log_colored(auth, Color::Red, "error", log)
+}
+fn log_warning(auth: &str, log: impl Display) -> std::io::Result<()> {
+    if !log_enabled(LogLevel::Warning) {
+        return Ok(());
+    }
+    log_colored(auth, Color::Yellow, "warning", log)
+}
+fn log_colored(auth: &str, color: Color, level: &str, log: impl Display) -> std::io::Result<()> {
     let stderr = StandardStream::stderr(ColorChoice::Auto);
     let mut lock = stderr.lock();
     if !auth.is_empty() {
         write!(lock, "[{}] ", auth)?;
     }
-    lock.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true))?;
-    write!(lock, "error")?;
+    lock.set_color(ColorSpec::new().set_fg(Some(color)).set_bold(true))?;
+    write!(lock, "{}", level)?;
     lock.reset()?;
     writeln!(lock, ": {}", log)
 }
 
-enum DomainRule {
-    Direct(Domains),
-    Proxy(Domains),
+struct Credential {
+    username: String,
+    password: String,
 }
 
-impl DomainRule {
-    fn get_connection(&self, uri: &[u8]) -> Option<Connection> {
-        match self {
-            DomainRule::Direct(domains) => {
-                if domains.contain_host(uri) {
-                    Some(Connection::Direct)
-                } else {
-                    None
-                }
+struct Outbound {
+    name: String,
+    addr: SocketAddr,
+    credential: Option<Credential>,
+}
+
+impl Outbound {
+    async fn connect(&self, auth: &str) -> anyhow::Result<Socks5Stream<TcpStream>> {
+        let result = match &self.credential {
+            None => Socks5Stream::connect(self.addr, auth).await,
+            Some(credential) => {
+                Socks5Stream::connect_with_password(
+                    self.addr,
+                    auth,
+                    &credential.username,
+                    &credential.password,
+                )
+                .await
             }
-            DomainRule::Proxy(domains) => {
-                if domains.contain_host(uri) {
-                    Some(Connection::Socks5)
-                } else {
-                    None
-                }
+        };
+        result.map_err(|e| match e {
+            tokio_socks::Error::PasswordAuthFailure(_) => {
+                anyhow!("outbound `{}` rejected the credential: {}", self.name, e)
             }
+            tokio_socks::Error::AuthorizationRequired
+            | tokio_socks::Error::NoAcceptableAuthMethods => anyhow!(
+                "outbound `{}` requires a supported authentication: {}",
+                self.name,
+                e
+            ),
+            e => anyhow!("outbound `{}`: {}", self.name, e),
+        })
+    }
+}
+
+struct Listener {
+    addr: SocketAddr,
+    // Index of the profile in `Env::profiles`.
+    profile: usize,
+}
+
+struct Profile {
+    // `None` for the top-level rules.
+    name: Option<String>,
+    rules: Rules,
+    // Keys of the rules in the config, like `rules[0]` or `blockips`.
+    keys: Vec<String>,
+}
+
+struct Env {
+    listeners: Vec<Listener>,
+    socks5listen: Option<SocketAddr>,
+    // Indexed by `Action::Proxy`.
+    outbounds: Vec<Arc<Outbound>>,
+    clients: Clients,
+    // The first profile is of the top-level `rules`.
+    profiles: Vec<Profile>,
+    subscriptions: Vec<Subscription>,
+    // Files the env is loaded from, like the config files, the list files, geoip.dat and
+    // geosite.dat, which may not exist.
+    sources: Vec<PathBuf>,
+}
+
+impl Env {
+    // The action in the log format, like `PROXY(default)`.
+    fn describe(&self, action: Action) -> String {
+        match action {
+            Action::Direct => "DIRECT".to_string(),
+            Action::Proxy(outbound) => format!("PROXY({})", self.outbounds[outbound].name),
+            Action::Block(mode) => format!("BLOCK({})", format!("{:?}", mode).to_lowercase()),
         }
     }
+
+    // Profile of the listener of the address, the top-level one if it's not listened any more.
+    fn profile(&self, addr: SocketAddr) -> usize {
+        self.listeners
+            .iter()
+            .find(|listener| listener.addr == addr)
+            .map_or(0, |listener| listener.profile)
+    }
 }
 
-enum IpRule {
-    Direct(Ips),
-    Proxy(Ips),
+// What serves a connection accepted by a listener. The env is the current one when the
+// connection is accepted, so reloading the env doesn't affect the existing connections.
+#[derive(Clone)]
+struct Inbound {
+    env: Arc<Env>,
+    profile: usize,
 }
 
-impl IpRule {
-    fn get_connection(&self, ip: IpAddr) -> Connection {
-        match self {
-            IpRule::Direct(ips) => {
-                if ips.contain_ip(ip) {
-                    Connection::Direct
-                } else {
-                    Connection::Socks5
-                }
-            }
-            IpRule::Proxy(ips) => {
-                if ips.contain_ip(ip) {
-                    Connection::Socks5
-                } else {
-                    Connection::Direct
-                }
+impl Inbound {
+    fn rules(&self) -> &Rules {
+        &self.env.profiles[self.profile].rules
+    }
+}
+
+// The env of the new connections, replaced as a whole when the config is reloaded.
+type CurrentEnv = RwLock<Arc<Env>>;
+
+fn snapshot(current: &CurrentEnv) -> Arc<Env> {
+    current.read().expect("env lock poisoned").clone()
+}
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Path of the config file [default: $HOME/.h2sr/config.toml]
+    #[arg(long, env = "H2SR_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    /// Path of geoip.dat instead of `geoip` in the config [default: next to the config file]
+    #[arg(long, global = true)]
+    geoip: Option<PathBuf>,
+    /// Path of geosite.dat instead of `geosite` in the config [default: next to the config file]
+    #[arg(long, global = true)]
+    geosite: Option<PathBuf>,
+    /// Listen on the address instead of `listen` in the config
+    #[arg(long, global = true)]
+    listen: Option<SocketAddr>,
+    /// Print the logs of the level and the more severe ones
+    #[arg(long, value_enum, default_value = "info", global = true)]
+    log_level: LogLevel,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validate the config, geoip.dat and geosite.dat without serving
+    Check,
+    /// Compile the rules into the rule database used on start
+    Compile,
+    /// Show how the action of a host is decided
+    Route {
+        /// `host` or `host:port`
+        target: String,
+        /// Use the rules of the profile instead of the top-level ones
+        #[arg(long)]
+        profile: Option<String>,
+    },
+}
+
+impl Cli {
+    fn options(&self) -> Options {
+        let config = self.config.clone().unwrap_or_else(|| match h2sr_dir() {
+            Some(dir) => dir.join("config.toml"),
+            None => {
+                log_error(
+                    "",
+                    "unable to get the home directory, set `--config` instead",
+                )
+                .expect(ERROR_WHILE_LOGGING);
+                std::process::exit(2)
             }
+        });
+        Options {
+            config,
+            geoip: self.geoip.clone(),
+            geosite: self.geosite.clone(),
+            listen: self.listen,
         }
     }
 }
-struct Env {
-    listen: SocketAddr,
-    socks5addr: SocketAddr,
-    blockdomains: Domains,
-    blockips: Ips,
-    domain_rule: DomainRule,
-    ip_rule: IpRule,
-}
-
-#[derive(Deserialize)]
-struct Config {
-    listen: SocketAddr,
-    socks5addr: SocketAddr,
-    proxydomains: Option<Vec<String>>,
-    proxyips: Option<Vec<String>>,
-    directdomains: Option<Vec<String>>,
-    directips: Option<Vec<String>>,
-    #[serde(default = "Vec::new")]
-    blockips: Vec<String>,
-    #[serde(default = "Vec::new")]
-    blockdomains: Vec<String>,
-}
-
-const ILLEGAL_CIDR: &'static str = "illegal CIDR";
-fn cidr_to_ipnet(cider: &h2sr::ipgeo::CIDR) -> IpNet {
-    let ip = cider.get_ip();
-    let prefix = cider.get_prefix() as u8;
-    if let Ok(ipv6_bytes) = <[u8; 16]>::try_from(ip) {
-        IpNet::V6(Ipv6Net::new(ipv6_bytes.into(), prefix).expect(ILLEGAL_CIDR))
-    } else if let Ok(ipv4_bytes) = <[u8; 4]>::try_from(ip) {
-        IpNet::V4(Ipv4Net::new(ipv4_bytes.into(), prefix).expect(ILLEGAL_CIDR))
-    } else {
-        panic!("{}", ILLEGAL_CIDR)
-    }
-}
-const GEO_PREFIX: &'static str = "geo:";
-fn to_ipnets_vec<'a, F: Fn() -> GeoIPList>(
-    ip_strings: impl Iterator<Item = &'a String>,
-    geoip_list: &unsync::Lazy<GeoIPList, F>,
-) -> Vec<IpNet> {
-    let mut result = Vec::new();
-    for ip_string in ip_strings {
-        let ip_str = ip_string.as_str();
-        if ip_str.starts_with(GEO_PREFIX) {
-            let geo = &ip_str[GEO_PREFIX.len()..];
-            let mut matched_ipnet = geoip_list
-                .get_entry()
-                .iter()
-                .filter(|geoip| geoip.get_country_code().eq_ignore_ascii_case(geo))
-                .flat_map(|geoip| geoip.get_cidr().iter().map(cidr_to_ipnet))
-                .collect::<Vec<_>>();
-            if matched_ipnet.len() == 0 {
-                let mut stderr = StandardStream::stderr(ColorChoice::Auto);
-                (|| -> std::io::Result<()> {
-                    stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
-                    write!(stderr, "warning")?;
-                    stderr.reset()?;
-                    writeln!(stderr, ": geo `{}` not found", geo)
-                })().expect(ERROR_WHILE_LOGGING);
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    LOG_LEVEL
+        .set(cli.log_level)
+        .unwrap_or_else(|_| unreachable!("log level is set once"));
+    let options = cli.options();
+    match cli.command {
+        None => {}
+        Some(Command::Check) => std::process::exit(check(&options)),
+        Some(Command::Compile) => std::process::exit(compile(&options)),
+        Some(Command::Route { target, profile }) => {
+            std::process::exit(explain(&options, &target, profile.as_deref()).await)
+        }
+    }
+
+    let (env, diagnostics) = load_env(&options);
+    report(&diagnostics);
+    let env = env.unwrap_or_else(|| std::process::exit(1));
+    fdlimit::raise_fd_limit();
+    let listeners: Vec<_> = env.listeners.iter().map(|listener| listener.addr).collect();
+    let socks5listen = env.socks5listen;
+    let current: &'static CurrentEnv = Box::leak(Box::new(RwLock::new(Arc::new(env))));
+
+    let options: &'static Options = Box::leak(Box::new(options));
+    let (reloads, receiver) = mpsc::unbounded_channel();
+    tokio::task::spawn(run_reloads(options, current, receiver));
+    tokio::task::spawn(watch(current, reloads.clone()));
+    #[cfg(unix)]
+    tokio::task::spawn(reload_on_hangup(reloads.clone()));
+    tokio::task::spawn(update_subscriptions(options, current, reloads));
+
+    if let Some(addr) = socks5listen {
+        tokio::task::spawn(async move {
+            if let Err(e) = listen_socks5(current, addr).await {
+                log_error("", e).expect(ERROR_WHILE_LOGGING);
             }
-            result.append(&mut matched_ipnet);
-        } else if let Ok(ipnet) = ip_str.parse::<IpNet>() {
-            result.push(ipnet)
-        } else {
-            panic!("illegal ip: `{}`", ip_str)
+        });
+    }
+
+    let listeners = listeners.into_iter().map(|addr| async move {
+        if let Err(e) = listen(current, addr).await {
+            log_error(&addr.to_string(), e).expect(ERROR_WHILE_LOGGING);
         }
+    });
+    join_all(listeners).await;
+}
+
+fn report(diagnostics: &Diagnostics) {
+    for warning in &diagnostics.warnings {
+        log_warning("", warning).expect(ERROR_WHILE_LOGGING);
+    }
+    for error in &diagnostics.errors {
+        log_error("", error).expect(ERROR_WHILE_LOGGING);
+    }
+}
+
+// Validate the config, geoip.dat and geosite.dat without serving, the exit code is non-zero if
+// there is any error.
+fn check(options: &Options) -> i32 {
+    let (env, diagnostics) = config::check(options);
+    report(&diagnostics);
+    if let Some(env) = env {
+        report_sizes(&env);
+    }
+    if diagnostics.errors.is_empty() {
+        println!(
+            "Config is valid, with {} warning(s)",
+            diagnostics.warnings.len()
+        );
+        0
+    } else {
+        println!("Found {} error(s)", diagnostics.errors.len());
+        1
     }
-    ;
-    result
 }
-fn add_gfwlist()
-fn create_domains<I: Iterator>(domains: I) -> Domains where I::Item: AsRef<[u8]> {
-    let mut result = Domains::default();
-    for domain in domains {
-        if domain == b":gfwlist" {
 
+// Build the rules like `check` and write them to the rule database, which is used on start until
+// any of their sources is changed.
+fn compile(options: &Options) -> i32 {
+    let (result, diagnostics) = config::compile(options);
+    report(&diagnostics);
+    match result {
+        Ok(Some(_)) => {
+            println!("Compiled the rules into `{}`", options.database().display());
+            0
+        }
+        Ok(None) => {
+            println!("Found {} error(s)", diagnostics.errors.len());
+            1
+        }
+        Err(e) => {
+            log_error("", format_args!("{:#}", e)).expect(ERROR_WHILE_LOGGING);
+            1
         }
     }
 }
-fn load_geoip(geoip_path: &Path) -> GeoIPList {
-    let mut buf_reader =
-        BufReader::new(fs::File::open(geoip_path).expect("unable to open geoip.dat file"));
-    let mut proto_in = protobuf::CodedInputStream::from_buffered_reader(&mut buf_reader);
-    GeoIPList::parse_from(&mut proto_in).expect("error while parsing geoip.dat")
+
+// Print the number of the domains in the trie of each rule, and the memory it takes.
+fn report_sizes(env: &Env) {
+    for profile in &env.profiles {
+        for (key, rule) in profile.keys.iter().zip(&profile.rules.rules) {
+            if let Some(domains) = &rule.domains {
+                let (len, size) = domains.trie_size();
+                println!(
+                    "`{}`: {} domain(s), {:.1} KiB",
+                    key,
+                    len,
+                    size as f64 / 1024.0
+                );
+            }
+        }
+    }
 }
-fn load_env() -> Env {
-    let mut h2sr_dir: PathBuf = directories::UserDirs::new()
-        .expect("unable to get user directory")
-        .home_dir()
-        .to_path_buf();
-    h2sr_dir.push(".h2sr");
 
-    let config: Config = {
-        let mut path = h2sr_dir.clone();
-        path.push("config.toml");
-        path.shrink_to_fit();
-        let bytes = fs::read(path).expect("unable to read config.toml file");
-        toml::from_slice(&bytes).expect("config file error")
+// Print how the action of `host[:port]` is decided by the rules of the profile, the top-level one
+// if not set.
+async fn explain(options: &Options, target: &str, profile: Option<&str>) -> i32 {
+    let auth = match target.parse::<Authority>() {
+        Ok(auth) => auth,
+        Err(e) => {
+            log_error(target, e).expect(ERROR_WHILE_LOGGING);
+            return 2;
+        }
+    };
+    let (env, diagnostics) = load_env(options);
+    report(&diagnostics);
+    let env = match env {
+        Some(env) => env,
+        None => return 1,
     };
-    let geoip = {
-        let mut path = h2sr_dir;
-        path.push("geoip.dat");
-        path.shrink_to_fit();
-        unsync::Lazy::<GeoIPList, _>::new(move || load_geoip(&path))
+    let profile = match env.profiles.iter().find(|p| p.name.as_deref() == profile) {
+        Some(profile) => profile,
+        None => {
+            log_error(
+                "",
+                format_args!("profile `{}` not found", profile.unwrap_or("")),
+            )
+            .expect(ERROR_WHILE_LOGGING);
+            return 1;
+        }
     };
 
-    let blockdomains = Domains::from_strs(config.blockdomains.iter().map(|v| v.as_str()))
-        .expect("error while parsing `blockdomains`");
+    let host = auth.host().trim_start_matches('[').trim_end_matches(']');
+    let port = auth.port_u16().unwrap_or(443);
+    let explanation = profile.rules.explain(host, || lookup(host, port)).await;
+    let rule = |index: usize| {
+        format!(
+            "`{}` ({})",
+            profile.keys[index],
+            env.describe(profile.rules.rules[index].action)
+        )
+    };
+    let from = |origin: Option<String>| origin.map_or(String::new(), |o| format!(" from {}", o));
+    for step in explanation.steps {
+        match step {
+            Step::NotMatched { rule: index } => println!("{}: not matched", rule(index)),
+            Step::Skipped { rule: index } => println!("{}: skipped (IP literal)", rule(index)),
+            Step::DomainMatched {
+                rule: index,
+                entry,
+                origin,
+            } => println!(
+                "{}: matched by domain `{}`{}",
+                rule(index),
+                entry,
+                from(origin)
+            ),
+            Step::Resolved(ip) => println!("Resolved {} to {}", host, ip),
+            Step::IpMatched {
+                rule: index,
+                ipnet,
+                origin,
+            } => println!(
+                "{}: matched by ip range {}{}",
+                rule(index),
+                ipnet,
+                from(origin)
+            ),
+            Step::Default => println!("No rule matched, taking the default"),
+        }
+    }
+    match explanation.action {
+        Ok(action) => {
+            println!("{}: {}", env.describe(action), auth);
+            0
+        }
+        Err(e) => {
+            log_error(auth.as_str(), e).expect(ERROR_WHILE_LOGGING);
+            1
+        }
+    }
+}
 
-    let blockips = Ips::from_ipnets(to_ipnets_vec(config.blockips.iter(), &geoip).into_iter())
-        .expect("error while parsing `blockips`");
+// How the env is rebuilt, like `load_env` or `recompile`.
+type Load = fn(&Options) -> (Option<Env>, Diagnostics);
+const RELOADS_STOPPED: &str = "reloads stopped";
 
-    let domain_rule = match (config.directdomains, config.proxydomains) {
-        (Some(directdomains), None) => DomainRule::Direct(
-            Domains::from_strs(directdomains.iter().map(|s| s.as_str()))
-                .expect("error while parsing `directdomains`"),
-        ),
-        (None, Some(proxydomains)) => DomainRule::Proxy(
-            Domains::from_strs(proxydomains.iter().map(|s| s.as_str()))
-                .expect("error while parsing `proxydomains`"),
-        ),
+// Run the reloads sent by `watch`, `update_subscriptions` and `reload_on_hangup` one at a time, so
+// the env is replaced in the order they are triggered.
+async fn run_reloads(
+    options: &'static Options,
+    current: &'static CurrentEnv,
+    mut reloads: mpsc::UnboundedReceiver<Load>,
+) {
+    while let Some(load) = reloads.recv().await {
+        reload(options, current, load).await;
+    }
+}
 
-        _ => panic!("only exact one of `directdomains` and `proxydoamins` should be set"),
-    };
+// Rebuild the env by `load`, the current one is kept if there is any error.
+async fn reload(options: &'static Options, current: &'static CurrentEnv, load: Load) {
+    let result = tokio::task::spawn_blocking(move || load(options))
+        .await
+        .expect("panicked while loading config");
+    let (env, diagnostics) = result;
+    report(&diagnostics);
+    match env {
+        Some(env) => {
+            let old = snapshot(current);
+            let addrs = |env: &Env| {
+                let mut addrs: Vec<_> = env.listeners.iter().map(|l| l.addr).collect();
+                addrs.sort();
+                (addrs, env.socks5listen)
+            };
+            if addrs(&old) != addrs(&env) {
+                log_warning("", "listen addresses are changed, which requires a restart")
+                    .expect(ERROR_WHILE_LOGGING);
+            }
+            *current.write().expect("env lock poisoned") = Arc::new(env);
+            log_info!("Config reloaded");
+        }
+        None => log_warning("", "keeping the current config").expect(ERROR_WHILE_LOGGING),
+    }
+}
 
-    let ip_rule = match (config.directips, config.proxyips) {
-        (Some(directips), None) => IpRule::Direct(
-            Ips::from_ipnets(to_ipnets_vec(directips.iter(), &geoip).into_iter())
-                .expect("error while parsing `directips`"),
-        ),
-        (None, Some(proxyips)) => IpRule::Proxy(
-            Ips::from_ipnets(to_ipnets_vec(proxyips.iter(), &geoip).into_iter())
-                .expect("error while parsing `proxyips`"),
-        ),
+// Like `load_env`, but the rule database is compiled again. The env is built from the files
+// instead if the database can't be written.
+fn recompile(options: &Options) -> (Option<Env>, Diagnostics) {
+    let (result, diagnostics) = config::compile(options);
+    match result {
+        Ok(env) => (env, diagnostics),
+        Err(e) => {
+            log_error("", format_args!("{:#}", e)).expect(ERROR_WHILE_LOGGING);
+            load_env(options)
+        }
+    }
+}
 
-        _ => panic!("only exact one of `directips` and `proxyips` should be set"),
-    };
-    Env {
-        listen: config.listen,
-        socks5addr: config.socks5addr,
-        blockdomains,
-        blockips,
-        domain_rule,
-        ip_rule,
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// Reload when the modification time of any source of the env changes. The subscription caches
+// are left to `update_subscriptions`, which reloads after writing them.
+async fn watch(current: &'static CurrentEnv, reloads: mpsc::UnboundedSender<Load>) {
+    let mut last: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let env = snapshot(current);
+        let mut changed = false;
+        for source in &env.sources {
+            if env.subscriptions.iter().any(|s| s.cache == *source) {
+                continue;
+            }
+            let modified = tokio::fs::metadata(source)
+                .await
+                .and_then(|m| m.modified())
+                .ok();
+            // The sources of a reloaded env are only compared from then on.
+            if let Some(last) = last.insert(source.clone(), modified) {
+                changed |= last != modified;
+            }
+        }
+        if changed {
+            reloads.send(load_env).expect(RELOADS_STOPPED);
+        }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    let env: &'static _ = Box::leak(Box::new(load_env()));
-    fdlimit::raise_fd_limit();
-    let client = HttpClient::new();
+const SUBSCRIPTION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const SUBSCRIPTION_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
-    let make_service = make_service_fn(move |_| {
-        let client = client.clone();
-        async move { Ok::<_, Infallible>(service_fn(move |req| proxy(env, client.clone(), req))) }
-    });
+// Download the subscriptions when they are due, and reload if any of them is updated. A failed
+// one is retried later, while its cache is still used.
+async fn update_subscriptions(
+    options: &'static Options,
+    current: &'static CurrentEnv,
+    reloads: mpsc::UnboundedSender<Load>,
+) {
+    let mut failed: HashMap<String, Instant> = HashMap::new();
+    let mut interval = tokio::time::interval(SUBSCRIPTION_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let env = snapshot(current);
+        let mut updated = false;
+        for subscription in &env.subscriptions {
+            let retrying = failed
+                .get(&subscription.name)
+                .is_some_and(|time| time.elapsed() < SUBSCRIPTION_RETRY_INTERVAL);
+            if retrying || !subscription.is_due() {
+                continue;
+            }
+            match subscription.download(&env.outbounds).await {
+                Ok(()) => {
+                    failed.remove(&subscription.name);
+                    log_info!("Subscription `{}` updated", subscription.name);
+                    updated = true;
+                }
+                Err(e) => {
+                    failed.insert(subscription.name.clone(), Instant::now());
+                    log_error(
+                        "",
+                        format_args!(
+                            "unable to update subscription `{}`: {:#}",
+                            subscription.name, e
+                        ),
+                    )
+                    .expect(ERROR_WHILE_LOGGING);
+                }
+            }
+        }
+        if updated {
+            // The rule database is out of date once the caches are updated.
+            let load: Load = if options.database().exists() {
+                recompile
+            } else {
+                load_env
+            };
+            reloads.send(load).expect(RELOADS_STOPPED);
+        }
+    }
+}
 
-    let server = Server::bind(&env.listen).serve(make_service);
+#[cfg(unix)]
+async fn reload_on_hangup(reloads: mpsc::UnboundedSender<Load>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => return log_error("", e).expect(ERROR_WHILE_LOGGING),
+    };
+    while hangup.recv().await.is_some() {
+        reloads.send(load_env).expect(RELOADS_STOPPED);
+    }
+}
 
-    println!("Listening on http://{}", env.listen);
+// Serve both HTTP and SOCKS5 clients on the same address.
+async fn listen(current: &'static CurrentEnv, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log_info!("Listening on http://{} and socks5://{}", addr, addr);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let env = snapshot(current);
+                let profile = env.profile(addr);
+                tokio::task::spawn(serve(Inbound { env, profile }, stream));
+            }
+            Err(e) => log_error("", e).expect(ERROR_WHILE_LOGGING),
+        }
+    }
+}
 
-    if let Err(e) = server.await {
-        log_error("", e)
-            .expect(ERROR_WHILE_LOGGING);
+// A SOCKS5 client starts with its version, while an HTTP one starts with an ASCII method.
+async fn serve(inbound: Inbound, stream: TcpStream) {
+    let mut first = [0u8; 1];
+    match stream.peek(&mut first).await {
+        Ok(0) => {}
+        Ok(_) if first[0] == socks5::VERSION => serve_socks5(inbound, stream).await,
+        Ok(_) => {
+            // Errors of the connection, like the reset of the service, are not logged, the same
+            // as hyper's `Server`.
+            let _ = Http::new()
+                .serve_connection(stream, service_fn(move |req| proxy(inbound.clone(), req)))
+                .with_upgrades()
+                .await;
+        }
+        Err(e) => log_error("", e).expect(ERROR_WHILE_LOGGING),
     }
 }
 
-async fn proxy(
-    env: &'static Env,
-    client: HttpClient,
-    req: Request<Body>,
-) -> Result<Response<Body>, hyper::Error> {
+async fn proxy(inbound: Inbound, req: Request<Body>) -> Result<Response<Body>, ConnectionReset> {
     // println!("req: {:?}", req);
 
-    if Method::CONNECT == req.method() {
+    let result = if Method::CONNECT == req.method() {
         // Received an HTTP request like:
         // ```
         // CONNECT www.domain.com:443 HTTP/1.1
@@ -340,26 +770,116 @@ async fn proxy(
         // Note: only after client received an empty body with STATUS_OK can the
         // connection be upgraded, so we can't return a response inside
         // `on_upgrade` future.
-        match connect(env, req).await {
-            Ok(()) => Ok(Response::new(Body::empty())),
-            Err((code, auth, err)) => {
-                let err = err.to_string();
-                log_error(&auth, &err).expect(ERROR_WHILE_LOGGING);
-                Ok(Response::builder()
-                    .status(code)
-                    .body(err.into())
-                    .expect("failed to create http response"))
+        connect(inbound, req).await
+    } else {
+        forward(inbound, req).await
+    };
+    match result {
+        Ok(Some(response)) => Ok(response),
+        Ok(None) => Err(ConnectionReset),
+        Err((code, auth, err)) => {
+            let err = err.to_string();
+            log_error(&auth, &err).expect(ERROR_WHILE_LOGGING);
+            Ok(Response::builder()
+                .status(code)
+                .body(err.into())
+                .expect("failed to create http response"))
+        }
+    }
+}
+
+// Decide the action of host:port by the rules, the host is resolved only if an IP rule is reached.
+async fn route(rules: &Rules, host: &str, port: u16) -> anyhow::Result<Action> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    rules.action(host, || lookup(host, port)).await
+}
+
+async fn lookup(host: &str, port: u16) -> anyhow::Result<IpAddr> {
+    lookup_host((host, port))
+        .await?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or_else(|| anyhow!("no ip found"))
+}
+
+type ProxyError = (http::StatusCode, String, anyhow::Error);
+
+// Returned by the service to make hyper drop the connection without any response.
+#[derive(Debug)]
+struct ConnectionReset;
+
+impl Display for ConnectionReset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection reset")
+    }
+}
+
+impl std::error::Error for ConnectionReset {}
+
+// Reject the blocked request as `mode`, `None` if the connection should be reset.
+fn block(auth: &str, mode: BlockMode, req: Request<Body>) -> Option<Response<Body>> {
+    log_info!("BLOCK {}", auth);
+    match mode {
+        BlockMode::Forbidden => Some(
+            Response::builder()
+                .status(http::StatusCode::FORBIDDEN)
+                .body(format!("{} is blocked", auth).into())
+                .expect("failed to create http response"),
+        ),
+        BlockMode::Reset => None,
+        BlockMode::Close => {
+            if Method::CONNECT == req.method() {
+                // The upgraded connection is dropped, and closed, as soon as it's established.
+                tokio::task::spawn(hyper::upgrade::on(req));
             }
+            Some(
+                Response::builder()
+                    .header(http::header::CONNECTION, "close")
+                    .body(Body::empty())
+                    .expect("failed to create http response"),
+            )
         }
-    } else {
-        client.request(req).await
     }
 }
 
+// Forward a plain HTTP request, which has an absolute URI like `http://www.domain.com/path`.
+async fn forward(
+    inbound: Inbound,
+    req: Request<Body>,
+) -> Result<Option<Response<Body>>, ProxyError> {
+    let Inbound { env, .. } = &inbound;
+    let uri = req.uri();
+    let auth = uri.authority().cloned().ok_or_else(|| {
+        (
+            http::StatusCode::BAD_REQUEST,
+            String::new(),
+            anyhow!("request host is illegal: '{}'", uri),
+        )
+    })?;
+    let bad_gateway = |e: anyhow::Error| (http::StatusCode::BAD_GATEWAY, auth.to_string(), e);
+
+    let action = route(inbound.rules(), auth.host(), auth.port_u16().unwrap_or(80))
+        .await
+        .map_err(bad_gateway)?;
+    let response = match action {
+        Action::Direct => {
+            log_info!("DIRECT: {}", auth);
+            env.clients.direct.request(req).await
+        }
+        Action::Proxy(outbound) => {
+            log_info!("PROXY({}): {}", env.outbounds[outbound].name, auth);
+            env.clients.proxies[outbound].request(req).await
+        }
+        Action::Block(mode) => return Ok(block(auth.as_str(), mode, req)),
+    };
+    response.map(Some).map_err(|e| bad_gateway(e.into()))
+}
+
 async fn connect(
-    env: &'static Env,
+    inbound: Inbound,
     req: Request<Body>,
-) -> Result<(), (http::StatusCode, String, anyhow::Error)> {
+) -> Result<Option<Response<Body>>, ProxyError> {
+    let env = &inbound.env;
     let uri = req.uri();
     let auth = uri.authority().ok_or_else(|| {
         (
@@ -369,57 +889,406 @@ async fn connect(
         )
     })?;
 
-    let host = auth.host().as_bytes();
-    let connection = if let Some(ipaddr) = std::str::from_utf8(host)
-        .ok()
-        .and_then(|s| s.parse::<IpAddr>().ok())
+    let connection = match route(inbound.rules(), auth.host(), auth.port_u16().unwrap_or(443)).await
     {
-        if env.blockips.contain_ip(ipaddr) {
-            println!("BLOCK {}", auth);
-            return Ok(());
-        } else {
-            env.ip_rule.get_connection(ipaddr)
-        }
-    } else if env.blockdomains.contain_host(host) {
-        println!("BLOCK {}", auth);
-        return Ok(());
-    } else if let Some(connection) = env.domain_rule.get_connection(host) {
-        connection
-    } else {
-        let auth = auth.as_str();
-        match lookup_host(auth).await {
-            Err(e) => return Err((http::StatusCode::BAD_GATEWAY, auth.to_string(), e.into())),
-            Ok(mut host) => match host.next() {
-                None => {
-                    return Err((
-                        http::StatusCode::BAD_GATEWAY,
-                        auth.to_string(),
-                        anyhow!("no ip found"),
-                    ))
-                }
-                Some(ip) => {
-                    if env.blockips.contain_ip(ip.ip()) {
-                        println!("BLOCK {}", auth);
-                        return Ok(());
-                    } else {
-                        env.ip_rule.get_connection(ip.ip())
-                    }
-                }
-            },
+        Ok(Action::Direct) => Connection::new(env, None),
+        Ok(Action::Proxy(outbound)) => Connection::new(env, Some(outbound)),
+        Ok(Action::Block(mode)) => {
+            let auth = auth.to_string();
+            return Ok(block(&auth, mode, req));
         }
+        Err(e) => return Err((http::StatusCode::BAD_GATEWAY, auth.to_string(), e)),
     };
 
     let auth = auth.to_string();
-    let socks5 = env.socks5addr;
     tokio::task::spawn(async move {
         match hyper::upgrade::on(req).await {
             Ok(upgraded) => {
-                if let Err(e) = connection.tunnel(&auth, upgraded, socks5).await {
+                if let Err(e) = connection.tunnel(&auth, upgraded).await {
                     log_error(&auth, e).expect(ERROR_WHILE_LOGGING);
                 };
             }
             Err(e) => log_error(&auth, e).expect(ERROR_WHILE_LOGGING),
         }
     });
+    Ok(Some(Response::new(Body::empty())))
+}
+
+// Serve SOCKS5 clients by the top-level rules.
+async fn listen_socks5(current: &'static CurrentEnv, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log_info!("Listening on socks5://{}", addr);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let env = snapshot(current);
+                tokio::task::spawn(serve_socks5(Inbound { env, profile: 0 }, stream));
+            }
+            Err(e) => log_error("", e).expect(ERROR_WHILE_LOGGING),
+        }
+    }
+}
+
+async fn serve_socks5(inbound: Inbound, mut stream: TcpStream) {
+    match socks5::accept(&mut stream).await {
+        Ok(target) => {
+            let auth = target.to_string();
+            if let Err(e) = socks5_connect(inbound, stream, &target, &auth).await {
+                log_error(&auth, e).expect(ERROR_WHILE_LOGGING);
+            }
+        }
+        Err(e) => log_error("", e).expect(ERROR_WHILE_LOGGING),
+    }
+}
+
+// Route the SOCKS5 `CONNECT` request like the HTTP `CONNECT` one.
+async fn socks5_connect(
+    inbound: Inbound,
+    mut stream: TcpStream,
+    target: &socks5::Target,
+    auth: &str,
+) -> anyhow::Result<()> {
+    let env = &inbound.env;
+    let connection = match route(inbound.rules(), &target.host, target.port).await {
+        Ok(Action::Direct) => Connection::new(env, None),
+        Ok(Action::Proxy(outbound)) => Connection::new(env, Some(outbound)),
+        Ok(Action::Block(mode)) => {
+            log_info!("BLOCK {}", auth);
+            match mode {
+                BlockMode::Forbidden => {
+                    socks5::reply(&mut stream, Reply::ConnectionNotAllowed).await?
+                }
+                BlockMode::Reset => {}
+                BlockMode::Close => socks5::reply(&mut stream, Reply::Succeeded).await?,
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            socks5::reply(&mut stream, Reply::HostUnreachable).await?;
+            return Err(e);
+        }
+    };
+    let mut server = match connection.open(auth).await {
+        Ok(server) => server,
+        Err(e) => {
+            let reply = match e.downcast_ref::<std::io::Error>() {
+                Some(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    Reply::ConnectionRefused
+                }
+                _ => Reply::GeneralFailure,
+            };
+            socks5::reply(&mut stream, reply).await?;
+            return Err(e);
+        }
+    };
+    socks5::reply(&mut stream, Reply::Succeeded).await?;
+    Connection::tunnel_stream(auth, stream, &mut server).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        block, forward, load_env, serve, Cli, Clients, Command, Credential, Env, Inbound, LogLevel,
+        Outbound, Profile,
+    };
+    use clap::Parser;
+    use h2sr::rule::{Action, BlockMode, Rule, Rules};
+    use h2sr::{socks5, Domains};
+    use hyper::{Body, Request};
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_socks::tcp::Socks5Stream;
+
+    // The top-level profile of the env has the rules.
+    fn inbound(rules: Rules, outbounds: Vec<Arc<Outbound>>) -> Inbound {
+        let env = Env {
+            listeners: Vec::new(),
+            socks5listen: None,
+            clients: Clients::new(&outbounds),
+            outbounds,
+            profiles: vec![Profile {
+                name: None,
+                keys: Vec::new(),
+                rules,
+            }],
+            subscriptions: Vec::new(),
+            sources: Vec::new(),
+        };
+        Inbound {
+            env: Arc::new(env),
+            profile: 0,
+        }
+    }
+
+    // Read the HTTP request and respond the body.
+    async fn respond(stream: &mut TcpStream, body: &str) {
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(stream.read_u8().await.unwrap());
+        }
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn block_responses() {
+        let get = || Request::get("http://ads.com/").body(Body::empty()).unwrap();
+        let response = block("ads.com", BlockMode::Forbidden, get()).unwrap();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "ads.com is blocked");
+
+        assert!(block("ads.com", BlockMode::Reset, get()).is_none());
+
+        let response = block("ads.com", BlockMode::Close, get()).unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.headers()[http::header::CONNECTION], "close");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forward_by_rules() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = server.accept().await.unwrap();
+            respond(&mut stream, "direct").await;
+        });
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let outbound = Arc::new(Outbound {
+            name: "us".to_string(),
+            addr: proxy.local_addr().unwrap(),
+            credential: None,
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let target = socks5::accept(&mut stream).await.unwrap();
+            assert_eq!(target.to_string(), "proxied.test:80");
+            socks5::reply(&mut stream, socks5::Reply::Succeeded)
+                .await
+                .unwrap();
+            respond(&mut stream, "proxy").await;
+        });
+        let rules = Rules {
+            rules: vec![
+                Rule::domains(
+                    Domains::from_strs(["blocked.test"].iter().copied()).unwrap(),
+                    Action::Block(BlockMode::Forbidden),
+                ),
+                Rule::domains(
+                    Domains::from_strs(["proxied.test"].iter().copied()).unwrap(),
+                    Action::Proxy(0),
+                ),
+            ],
+            default: Action::Direct,
+        };
+        let inbound = inbound(rules, vec![outbound]);
+
+        let get = |uri: String| {
+            let inbound = inbound.clone();
+            async move {
+                let req = Request::get(uri).body(Body::empty()).unwrap();
+                let response = match forward(inbound, req).await {
+                    Ok(response) => response.unwrap(),
+                    Err((_, _, e)) => panic!("{}", e),
+                };
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        assert_eq!(
+            get("http://blocked.test/".to_string()).await,
+            (
+                http::StatusCode::FORBIDDEN,
+                "blocked.test is blocked".to_string()
+            )
+        );
+        assert_eq!(
+            get("http://proxied.test/".to_string()).await,
+            (http::StatusCode::OK, "proxy".to_string())
+        );
+        assert_eq!(
+            get(format!("http://{}/", server_addr)).await,
+            (http::StatusCode::OK, "direct".to_string())
+        );
+    }
+
+    // Serve the connections to the address by the rules blocking `blocked.test`.
+    async fn serve_blocked() -> SocketAddr {
+        let rules = Rules {
+            rules: vec![Rule::domains(
+                Domains::from_strs(["blocked.test"].iter().copied()).unwrap(),
+                Action::Block(BlockMode::Forbidden),
+            )],
+            default: Action::Direct,
+        };
+        let inbound = inbound(rules, Vec::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(inbound.clone(), stream));
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn serve_http() {
+        let mut stream = TcpStream::connect(serve_blocked().await).await.unwrap();
+        stream
+            .write_all(
+                b"GET http://blocked.test/ HTTP/1.1\r\n\
+                  Host: blocked.test\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{}",
+            response
+        );
+        assert!(
+            response.ends_with("blocked.test is blocked"),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_socks5() {
+        let error = Socks5Stream::connect(serve_blocked().await, "blocked.test:80")
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(error, tokio_socks::Error::ConnectionNotAllowedByRuleset),
+            "{}",
+            error
+        );
+    }
+
+    // Connect through a SOCKS5 server which selects `method` and rejects the credential.
+    async fn connect_rejected(credential: Option<Credential>, method: u8) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let outbound = Outbound {
+            name: "us".to_string(),
+            addr: listener.local_addr().unwrap(),
+            credential,
+        };
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 2];
+            stream.read_exact(&mut greeting).await.unwrap();
+            let mut methods = vec![0u8; greeting[1] as usize];
+            stream.read_exact(&mut methods).await.unwrap();
+            stream.write_all(&[5, method]).await.unwrap();
+            if method == 2 {
+                let mut auth = [0u8; 2];
+                stream.read_exact(&mut auth).await.unwrap();
+                let mut username = vec![0u8; auth[1] as usize + 1];
+                stream.read_exact(&mut username).await.unwrap();
+                let mut password = vec![0u8; *username.last().unwrap() as usize];
+                stream.read_exact(&mut password).await.unwrap();
+                stream.write_all(&[1, 1]).await.unwrap();
+            }
+        });
+        let error = outbound.connect("example.com:443").await.err().unwrap();
+        server.await.unwrap();
+        error.to_string()
+    }
+
+    #[tokio::test]
+    async fn reject_credential() {
+        let credential = Credential {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+        };
+        let error = connect_rejected(Some(credential), 2).await;
+        assert!(
+            error.starts_with("outbound `us` rejected the credential: "),
+            "{}",
+            error
+        );
+        let error = connect_rejected(None, 0xff).await;
+        assert!(
+            error.starts_with("outbound `us` requires a supported authentication: "),
+            "{}",
+            error
+        );
+    }
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("h2sr").chain(args.iter().copied())).unwrap()
+    }
+
+    // The only test reading `H2SR_CONFIG`, so setting it doesn't affect the others.
+    #[test]
+    fn config_path() {
+        std::env::remove_var("H2SR_CONFIG");
+        let cli = parse(&["check", "--config", "arg.toml"]);
+        assert!(matches!(cli.command, Some(Command::Check)));
+        assert_eq!(cli.options().config, PathBuf::from("arg.toml"));
+
+        std::env::set_var("H2SR_CONFIG", "env.toml");
+        let from_env = parse(&[]).options().config;
+        let from_arg = parse(&["--config", "arg.toml"]).options().config;
+        std::env::remove_var("H2SR_CONFIG");
+        assert_eq!(from_env, PathBuf::from("env.toml"));
+        assert_eq!(from_arg, PathBuf::from("arg.toml"));
+    }
+
+    #[test]
+    fn override_listen() {
+        let dir = std::env::temp_dir().join(format!("h2sr-listen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.toml");
+        fs::write(
+            &config,
+            "listen = \"127.0.0.1:8080\"\ndefault = \"direct\"\n",
+        )
+        .unwrap();
+        let config = config.to_str().unwrap();
+        let listen = |args: &[&str]| {
+            let (env, diagnostics) = load_env(&parse(args).options());
+            assert!(diagnostics.errors.is_empty());
+            let env = env.unwrap();
+            env.listeners.iter().map(|l| l.addr).collect::<Vec<_>>()
+        };
+        let (config_listen, arg_listen) = (
+            listen(&["--config", config]),
+            listen(&["--config", config, "--listen", "127.0.0.1:8081"]),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(config_listen, vec!["127.0.0.1:8080".parse().unwrap()]);
+        assert_eq!(arg_listen, vec!["127.0.0.1:8081".parse().unwrap()]);
+        assert!(Cli::try_parse_from(["h2sr", "--listen", "localhost"]).is_err());
+    }
+
+    #[test]
+    fn log_levels() {
+        assert!(parse(&[]).log_level == LogLevel::Info);
+        let warning = parse(&["route", "example.com", "--log-level", "warning"]).log_level;
+        assert!(warning.shows(LogLevel::Error) && warning.shows(LogLevel::Warning));
+        assert!(!warning.shows(LogLevel::Info));
+        let error = parse(&["--log-level", "error"]).log_level;
+        assert!(error.shows(LogLevel::Error) && !error.shows(LogLevel::Warning));
+        assert!(LogLevel::Info.shows(LogLevel::Info));
+        assert!(Cli::try_parse_from(["h2sr", "--log-level", "debug"]).is_err());
+    }
+}
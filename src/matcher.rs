@@ -0,0 +1,423 @@
+//! Domain matchers other than the suffix trie, which are combined into [`Domains`].
+//!
+//! [`Domains`]: crate::Domains
+
+use crate::{Domains, Error, Result};
+use aho_corasick::AhoCorasick;
+use regex::bytes::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+
+/// Hosts containing any of the keywords, like `google` matching `www.google.co.jp`.
+#[derive(Default)]
+pub struct Keywords {
+    keywords: Vec<String>,
+    // Built from the keywords before `built`, `None` if there is none. The ones added since are
+    // searched one by one.
+    automaton: Option<AhoCorasick>,
+    built: usize,
+    removed: Removed,
+}
+
+impl Keywords {
+    /// Add the keyword, which is matched right after, and merged into the automaton by
+    /// [`Keywords::build`].
+    pub fn add(&mut self, keyword: &str) -> Result<()> {
+        // An empty keyword would be found in every host.
+        if keyword.is_empty() {
+            return Err(Error::EmptyPattern);
+        }
+        validate(keyword.bytes())?;
+        self.keywords.push(keyword.to_ascii_lowercase());
+        Ok(())
+    }
+
+    /// Remove the keyword, which is no longer matched right after, and dropped from the automaton
+    /// by [`Keywords::build`]. Returns the index of the keyword removed.
+    pub fn remove(&mut self, keyword: &str) -> Option<usize> {
+        let keyword = keyword.to_ascii_lowercase();
+        let index = self.keywords.iter().position(|k| *k == keyword)?;
+        self.keywords.remove(index);
+        if index < self.built {
+            self.built -= 1;
+            self.removed.remove(index);
+        }
+        Some(index)
+    }
+
+    /// Build the automaton of all the keywords.
+    pub fn build(&mut self) -> Result<()> {
+        if self.pending() != 0 {
+            self.automaton = Self::automaton(&self.keywords)?;
+            self.built = self.keywords.len();
+            self.removed = Removed::default();
+        }
+        Ok(())
+    }
+
+    fn automaton(keywords: &[String]) -> Result<Option<AhoCorasick>> {
+        if keywords.is_empty() {
+            return Ok(None);
+        }
+        let automaton = AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .build(keywords)
+            .map_err(|_| Error::TooManyDomains)?;
+        Ok(Some(automaton))
+    }
+
+    /// Whether there is no keyword.
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_empty()
+    }
+
+    /// The number of the keywords added or removed since the last build.
+    pub fn pending(&self) -> usize {
+        self.keywords.len() - self.built + self.removed.len()
+    }
+
+    /// The keywords, in the order they are added.
+    pub fn patterns(&self) -> &[String] {
+        &self.keywords
+    }
+
+    /// The first keyword found in the host.
+    pub fn matched(&self, host: &[u8]) -> Option<&str> {
+        self.position(host)
+            .map(|index| self.keywords[index].as_str())
+    }
+
+    /// The index of the first keyword found in the host.
+    pub fn position(&self, host: &[u8]) -> Option<usize> {
+        let found = self.automaton.as_ref().and_then(|automaton| {
+            automaton
+                .find_overlapping_iter(host)
+                .find_map(|found| self.removed.index(found.pattern().as_usize()))
+        });
+        found.or_else(|| {
+            let pending = self.keywords[self.built..].iter().position(|keyword| {
+                host.windows(keyword.len())
+                    .any(|window| window.eq_ignore_ascii_case(keyword.as_bytes()))
+            })?;
+            Some(self.built + pending)
+        })
+    }
+}
+
+/// Hosts matching any of the glob patterns, in which `*` matches any characters, including `.`,
+/// and `?` matches one character, like `*.cdn.*.example.com` matching `a.cdn.b.example.com`.
+#[derive(Default)]
+pub struct Wildcards {
+    patterns: Vec<String>,
+}
+
+impl Wildcards {
+    /// Add the glob pattern, in which `*` matches any characters and `?` matches one.
+    pub fn add(&mut self, pattern: &str) -> Result<()> {
+        validate(pattern.bytes().filter(|&b| b != b'*' && b != b'?'))?;
+        self.patterns.push(pattern.to_ascii_lowercase());
+        Ok(())
+    }
+
+    /// Remove the pattern, returns the index of it.
+    pub fn remove(&mut self, pattern: &str) -> Option<usize> {
+        let pattern = pattern.to_ascii_lowercase();
+        let index = self.patterns.iter().position(|p| *p == pattern)?;
+        self.patterns.remove(index);
+        Some(index)
+    }
+
+    /// Whether there is no pattern.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// The patterns, in the order they are added.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// The first pattern matching the host.
+    pub fn matched(&self, host: &[u8]) -> Option<&str> {
+        self.position(host)
+            .map(|index| self.patterns[index].as_str())
+    }
+
+    /// The index of the first pattern matching the host.
+    pub fn position(&self, host: &[u8]) -> Option<usize> {
+        self.patterns
+            .iter()
+            .position(|pattern| glob(pattern.as_bytes(), host))
+    }
+}
+
+/// Hosts matching any of the regular expressions, which are searched case-insensitively in the
+/// host, so they should be anchored by `^` and `$` to match the whole host.
+#[derive(Default)]
+pub struct Regexes {
+    patterns: Vec<String>,
+    // Built from the patterns before the pending ones, `None` if there is none.
+    set: Option<RegexSet>,
+    // The last patterns, added since the set is built, which are matched one by one.
+    pending: Vec<Regex>,
+    removed: Removed,
+}
+
+impl Regexes {
+    /// Add the pattern, which is matched right after, and merged into the set by
+    /// [`Regexes::build`].
+    pub fn add(&mut self, pattern: &str) -> Result<()> {
+        // An empty pattern would match every host.
+        if pattern.is_empty() {
+            return Err(Error::EmptyPattern);
+        }
+        // Compiled alone for the error of the pattern, which the set doesn't tell.
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| Error::IllegalRegex(e.to_string()))?;
+        self.patterns.push(pattern.to_string());
+        self.pending.push(regex);
+        Ok(())
+    }
+
+    /// Remove the pattern, which is no longer matched right after, and dropped from the set by
+    /// [`Regexes::build`]. Returns the index of the pattern removed.
+    pub fn remove(&mut self, pattern: &str) -> Option<usize> {
+        let index = self.patterns.iter().position(|p| p == pattern)?;
+        let built = self.patterns.len() - self.pending.len();
+        self.patterns.remove(index);
+        if index < built {
+            self.removed.remove(index);
+        } else {
+            self.pending.remove(index - built);
+        }
+        Some(index)
+    }
+
+    /// Build the set of all the patterns.
+    pub fn build(&mut self) -> Result<()> {
+        if self.pending() != 0 {
+            self.set = Self::set(&self.patterns)?;
+            self.pending.clear();
+            self.removed = Removed::default();
+        }
+        Ok(())
+    }
+
+    fn set(patterns: &[String]) -> Result<Option<RegexSet>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        let set = RegexSetBuilder::new(patterns)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| Error::IllegalRegex(e.to_string()))?;
+        Ok(Some(set))
+    }
+
+    /// Whether there is no pattern.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// The number of the patterns added or removed since the last build.
+    pub fn pending(&self) -> usize {
+        self.pending.len() + self.removed.len()
+    }
+
+    /// The patterns, in the order they are added.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// The first pattern matching the host.
+    pub fn matched(&self, host: &[u8]) -> Option<&str> {
+        self.position(host)
+            .map(|index| self.patterns[index].as_str())
+    }
+
+    /// The index of the first pattern matching the host.
+    pub fn position(&self, host: &[u8]) -> Option<usize> {
+        let found = self.set.as_ref().and_then(|set| {
+            set.matches(host)
+                .iter()
+                .find_map(|id| self.removed.index(id))
+        });
+        found.or_else(|| {
+            let pending = self.pending.iter().position(|regex| regex.is_match(host))?;
+            Some(self.patterns.len() - self.pending.len() + pending)
+        })
+    }
+}
+
+// Ids of the patterns removed from a built automaton or set, which still has them until it's built
+// again. The other patterns are indexed by their ids minus the number of the removed ones before.
+#[derive(Default)]
+struct Removed(Vec<usize>);
+
+impl Removed {
+    // The index of the pattern of the id, `None` if it's removed.
+    fn index(&self, id: usize) -> Option<usize> {
+        match self.0.binary_search(&id) {
+            Ok(_) => None,
+            Err(before) => Some(id - before),
+        }
+    }
+
+    // Remove the pattern of the index, which is not removed yet.
+    fn remove(&mut self, index: usize) {
+        let mut id = index;
+        let mut before = 0;
+        while before < self.0.len() && self.0[before] <= id {
+            id += 1;
+            before += 1;
+        }
+        self.0.insert(before, id);
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Whether the entry is a glob pattern of [`Wildcards`].
+pub fn is_wildcard(entry: &str) -> bool {
+    entry.bytes().any(|b| b == b'*' || b == b'?')
+}
+
+fn validate(chars: impl Iterator<Item = u8>) -> Result<()> {
+    for ch in chars {
+        Domains::check_char(ch)?;
+    }
+    Ok(())
+}
+
+// Match by backtracking to the last `*`, which is linear to the product of the lengths at worst.
+fn glob(pattern: &[u8], host: &[u8]) -> bool {
+    let (mut p, mut h) = (0, 0);
+    // Positions after the last `*` and of the host it's matched to.
+    let mut star = None;
+    while h < host.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == host[h].to_ascii_lowercase()) {
+            p += 1;
+            h += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            p += 1;
+            star = Some((p, h));
+        } else if let Some((star_p, star_h)) = star {
+            p = star_p;
+            h = star_h + 1;
+            star = Some((star_p, h));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Keywords, Regexes, Wildcards};
+
+    #[test]
+    fn match_keywords() {
+        let mut keywords = Keywords::default();
+        keywords.add("google").unwrap();
+        keywords.add("ads").unwrap();
+        keywords.build().unwrap();
+        assert_eq!(keywords.matched(b"www.Google.co.jp"), Some("google"));
+        assert_eq!(keywords.matched(b"pagead.example.com"), None);
+        assert_eq!(keywords.matched(b"ads.example.com"), Some("ads"));
+        assert!(keywords.add("bad keyword").is_err());
+
+        // The removed keyword is skipped until the automaton is rebuilt, and the indexes of the
+        // rest are still right.
+        assert_eq!(keywords.remove("Google"), Some(0));
+        assert_eq!(keywords.remove("google"), None);
+        assert_eq!(keywords.matched(b"www.google.co.jp"), None);
+        assert_eq!(keywords.matched(b"ads.example.com"), Some("ads"));
+        assert_eq!(keywords.position(b"ads.example.com"), Some(0));
+        assert_eq!(keywords.pending(), 1);
+        keywords.build().unwrap();
+        assert_eq!(keywords.pending(), 0);
+        assert_eq!(keywords.position(b"ads.example.com"), Some(0));
+
+        // The keywords added since the build are matched before the next one.
+        keywords.add("Tracker").unwrap();
+        assert_eq!(keywords.pending(), 1);
+        assert_eq!(keywords.matched(b"a.TRACKER.net"), Some("tracker"));
+        assert_eq!(keywords.remove("tracker"), Some(1));
+        assert_eq!(keywords.matched(b"a.tracker.net"), None);
+        assert_eq!(keywords.pending(), 0);
+    }
+
+    #[test]
+    fn remove_before_build() {
+        let mut keywords = Keywords::default();
+        let mut regexes = Regexes::default();
+        for pattern in ["alpha", "beta", "gamma", "delta"].iter() {
+            keywords.add(pattern).unwrap();
+            regexes.add(&format!("^{}\\.", pattern)).unwrap();
+        }
+        keywords.build().unwrap();
+        regexes.build().unwrap();
+        assert_eq!(keywords.remove("gamma"), Some(2));
+        assert_eq!(keywords.remove("alpha"), Some(0));
+        assert_eq!(keywords.position(b"delta.com"), Some(1));
+        assert_eq!(keywords.remove("delta"), Some(1));
+        assert_eq!(regexes.remove(r"^gamma\."), Some(2));
+        assert_eq!(regexes.remove(r"^alpha\."), Some(0));
+        assert_eq!(regexes.position(b"delta.com"), Some(1));
+        assert_eq!(regexes.remove(r"^delta\."), Some(1));
+        for host in ["alpha.com", "gamma.com", "delta.com"].iter() {
+            assert_eq!(keywords.position(host.as_bytes()), None, "{}", host);
+            assert_eq!(regexes.position(host.as_bytes()), None, "{}", host);
+        }
+        assert_eq!(keywords.matched(b"beta.com"), Some("beta"));
+        assert_eq!(regexes.matched(b"beta.com"), Some(r"^beta\."));
+        assert_eq!((keywords.pending(), regexes.pending()), (3, 3));
+    }
+
+    #[test]
+    fn match_wildcards() {
+        let mut wildcards = Wildcards::default();
+        wildcards.add("*.cdn.*.example.com").unwrap();
+        wildcards.add("img?.example.org").unwrap();
+        let matched = |host: &str| wildcards.matched(host.as_bytes()).is_some();
+        assert!(matched("a.cdn.b.example.com"));
+        assert!(matched("x.y.cdn.b.c.example.com"));
+        assert!(!matched("cdn.b.example.com"));
+        assert!(!matched("a.cdn.example.com"));
+        assert!(matched("IMG1.example.org"));
+        assert!(!matched("img12.example.org"));
+        assert!(wildcards.add("*.bad!host.com").is_err());
+    }
+
+    #[test]
+    fn match_regexes() {
+        let mut regexes = Regexes::default();
+        regexes.add(r"^([^/]+\.)*google\.(com|co\.jp)$").unwrap();
+        regexes.add(r"^ad[0-9]+\.").unwrap();
+        regexes.build().unwrap();
+        assert_eq!(
+            regexes.matched(b"www.Google.co.jp"),
+            Some(r"^([^/]+\.)*google\.(com|co\.jp)$")
+        );
+        assert_eq!(regexes.matched(b"google.co.uk"), None);
+        assert_eq!(regexes.matched(b"ad12.example.com"), Some(r"^ad[0-9]+\."));
+        assert!(regexes.add("(unclosed").is_err());
+
+        regexes.add(r"^img[0-9]+\.").unwrap();
+        assert_eq!(regexes.pending(), 1);
+        assert_eq!(regexes.matched(b"IMG1.example.com"), Some(r"^img[0-9]+\."));
+        assert_eq!(regexes.remove(r"^ad[0-9]+\."), Some(1));
+        assert_eq!(regexes.matched(b"ad12.example.com"), None);
+        assert_eq!(regexes.position(b"img1.example.com"), Some(1));
+        assert_eq!(regexes.remove(r"^img[0-9]+\."), Some(1));
+        assert_eq!(regexes.matched(b"img1.example.com"), None);
+        assert_eq!(regexes.pending(), 1);
+        assert_eq!(regexes.position(b"www.google.com"), Some(0));
+        regexes.build().unwrap();
+        assert_eq!(regexes.pending(), 0);
+        assert_eq!(regexes.patterns().len(), 1);
+    }
+}
@@ -0,0 +1,113 @@
+// Where the entries of the domains and the IP ranges are from, which is kept apart from the
+// structures matched on the hot path, and only looked up to attribute a match.
+
+use crate::db::Reader;
+use crate::{Error, Result};
+use std::fmt;
+
+/// Where an entry is from, like a rule file with the line of the entry, or a geo tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Origin<'a> {
+    pub source: &'a str,
+    /// The line of the entry in the source, starting from 1.
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Origin<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.source, line),
+            None => write!(f, "{}", self.source),
+        }
+    }
+}
+
+/// The entry a host or an IP is matched by, and where it's from if it's added with an origin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Matched<'a, T> {
+    pub entry: T,
+    pub origin: Option<Origin<'a>>,
+}
+
+// Index of an origin in `Sources`, the source is 0 if unknown, and the index of it plus one
+// otherwise, the line is 0 if unknown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OriginIndex {
+    source: u32,
+    line: u32,
+}
+
+// Size of an encoded index.
+pub const ORIGIN_SIZE: usize = 8;
+
+impl OriginIndex {
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.source.to_le_bytes());
+        bytes.extend_from_slice(&self.line.to_le_bytes());
+    }
+
+    pub fn decode(bytes: &[u8]) -> OriginIndex {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        OriginIndex {
+            source: u32_at(0),
+            line: u32_at(4),
+        }
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.source == 0
+    }
+}
+
+// Names of the sources, each one is kept once.
+#[derive(Default)]
+pub struct Sources {
+    names: Vec<String>,
+}
+
+impl Sources {
+    pub fn index(&mut self, origin: Option<Origin>) -> OriginIndex {
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return OriginIndex::default(),
+        };
+        // The entries of a source are usually added together.
+        let source = match self.names.iter().rposition(|name| name == origin.source) {
+            Some(index) => index,
+            None => {
+                self.names.push(origin.source.to_string());
+                self.names.len() - 1
+            }
+        };
+        OriginIndex {
+            source: source as u32 + 1,
+            line: origin.line.map_or(0, |line| line as u32),
+        }
+    }
+
+    pub fn get(&self, index: OriginIndex) -> Option<Origin<'_>> {
+        let source = self.names.get((index.source as usize).checked_sub(1)?)?;
+        Some(Origin {
+            source,
+            line: Some(index.line as usize).filter(|line| *line > 0),
+        })
+    }
+
+    pub fn write_to(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(self.names.len() as u32).to_le_bytes());
+        for name in &self.names {
+            crate::db::write_bytes(bytes, name.as_bytes());
+        }
+    }
+
+    pub fn read_from(reader: &mut Reader) -> Result<Sources> {
+        let mut names = Vec::new();
+        for _ in 0..reader.len()? {
+            let name = std::str::from_utf8(reader.bytes()?)
+                .map_err(|e| Error::IllegalDatabase(e.to_string()))?;
+            names.push(name.to_string());
+        }
+        Ok(Sources { names })
+    }
+}
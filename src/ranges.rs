@@ -0,0 +1,297 @@
+// Disjoint IP networks sorted by their addresses, in which the one containing an address is found
+// by binary search. Each network may carry the origin of the entry it's from. The networks added
+// and removed later are kept in `Changes` until the ranges are rebuilt.
+
+use crate::db::{Buffer, Data, Reader};
+use crate::origin::{OriginIndex, ORIGIN_SIZE};
+use crate::{Error, Result};
+use ipnet::{Ipv4Net, Ipv6Net};
+use iprange::{IpRange, ToNetwork};
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub trait Net: Copy + Ord + iprange::IpNet {
+    type Addr: Ord + ToNetwork<Self>;
+    // Size of an encoded network, which is the address followed by the prefix length.
+    const SIZE: usize;
+
+    fn encode(&self, bytes: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> Option<Self>;
+    fn addr(&self) -> Self::Addr;
+    fn contains(&self, addr: &Self::Addr) -> bool;
+}
+
+impl Net for Ipv4Net {
+    type Addr = Ipv4Addr;
+    const SIZE: usize = 5;
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.network().octets());
+        bytes.push(self.prefix_len());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let addr = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+        Ipv4Net::new(addr, bytes[4]).ok()
+    }
+
+    fn addr(&self) -> Ipv4Addr {
+        self.network()
+    }
+
+    fn contains(&self, addr: &Ipv4Addr) -> bool {
+        Ipv4Net::contains(self, addr)
+    }
+}
+
+impl Net for Ipv6Net {
+    type Addr = Ipv6Addr;
+    const SIZE: usize = 17;
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.network().octets());
+        bytes.push(self.prefix_len());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(&bytes[..16]);
+        Ipv6Net::new(octets.into(), bytes[16]).ok()
+    }
+
+    fn addr(&self) -> Ipv6Addr {
+        self.network()
+    }
+
+    fn contains(&self, addr: &Ipv6Addr) -> bool {
+        Ipv6Net::contains(self, addr)
+    }
+}
+
+pub struct Ranges<N> {
+    nets: Data,
+    // Origins of the networks, by their indexes. Empty if all of them are unknown.
+    origins: Data,
+    net: PhantomData<N>,
+}
+
+impl<N: Net> Default for Ranges<N> {
+    fn default() -> Self {
+        Ranges {
+            nets: Data::default(),
+            origins: Data::default(),
+            net: PhantomData,
+        }
+    }
+}
+
+impl<N: Net> Ranges<N> {
+    // The networks contained by others are dropped, so the ones kept are disjoint.
+    pub fn new(nets: impl Iterator<Item = N>) -> Ranges<N> {
+        Ranges::with_origins(nets.map(|net| (net, OriginIndex::default())))
+    }
+
+    // Like `new`, with the origins of the networks.
+    pub fn with_origins(nets: impl Iterator<Item = (N, OriginIndex)>) -> Ranges<N> {
+        let mut nets: Vec<_> = nets.collect();
+        // A network sorts after the ones containing it, since CIDR networks are either nested or
+        // disjoint, it's contained by the last one kept if it's contained by any.
+        nets.sort_unstable();
+        let mut kept: Vec<(N, OriginIndex)> = Vec::with_capacity(nets.len());
+        for (net, origin) in nets {
+            if !kept
+                .last()
+                .is_some_and(|(last, _)| last.contains(&net.addr()))
+            {
+                kept.push((net, origin));
+            }
+        }
+        let nets = kept;
+        let mut bytes = Vec::with_capacity(nets.len() * N::SIZE);
+        for (net, _) in &nets {
+            net.encode(&mut bytes);
+        }
+        let mut origins = Vec::new();
+        if nets.iter().any(|(_, origin)| !origin.is_unknown()) {
+            origins.reserve_exact(nets.len() * ORIGIN_SIZE);
+            for (_, origin) in &nets {
+                origin.encode(&mut origins);
+            }
+        }
+        Ranges {
+            nets: Data::Owned(bytes),
+            origins: Data::Owned(origins),
+            net: PhantomData,
+        }
+    }
+
+    pub fn write_to(&self, bytes: &mut Vec<u8>) {
+        crate::db::write_bytes(bytes, &self.nets);
+        crate::db::write_bytes(bytes, &self.origins);
+    }
+
+    // Use the networks in the database in place, after checking they are valid and sorted.
+    pub fn read_from(reader: &mut Reader, buffer: &Buffer) -> Result<Ranges<N>> {
+        let ranges = Ranges {
+            nets: Data::Shared(buffer.clone(), reader.bytes_range()?),
+            origins: Data::Shared(buffer.clone(), reader.bytes_range()?),
+            net: PhantomData,
+        };
+        let illegal = || Error::IllegalDatabase("illegal IP ranges".to_string());
+        if !ranges.nets.len().is_multiple_of(N::SIZE)
+            || !(ranges.origins.is_empty() || ranges.origins.len() == ranges.len() * ORIGIN_SIZE)
+        {
+            return Err(illegal());
+        }
+        let mut last: Option<N> = None;
+        for bytes in ranges.nets.chunks(N::SIZE) {
+            let net = N::decode(bytes).ok_or_else(illegal)?;
+            if last.is_some_and(|last| last.addr() >= net.addr()) {
+                return Err(illegal());
+            }
+            last = Some(net);
+        }
+        Ok(ranges)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nets.len() / N::SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nets.is_empty()
+    }
+
+    fn get(&self, index: usize) -> N {
+        // Validated when built or read.
+        N::decode(&self.nets[index * N::SIZE..(index + 1) * N::SIZE]).unwrap()
+    }
+
+    // The network containing the address, which is the last one starting at or before it.
+    pub fn supernet(&self, addr: &N::Addr) -> Option<N> {
+        self.supernet_index(addr).map(|index| self.get(index))
+    }
+
+    pub fn supernet_index(&self, addr: &N::Addr) -> Option<usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.get(mid).addr() <= *addr {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let index = low.checked_sub(1)?;
+        if self.get(index).contains(addr) {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_with_origin(&self, index: usize) -> (N, OriginIndex) {
+        let origin = self
+            .origins
+            .get(index * ORIGIN_SIZE..(index + 1) * ORIGIN_SIZE)
+            .map_or_else(OriginIndex::default, OriginIndex::decode);
+        (self.get(index), origin)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = N> + '_ {
+        (0..self.len()).map(move |index| self.get(index))
+    }
+
+    pub fn iter_with_origins(&self) -> impl Iterator<Item = (N, OriginIndex)> + '_ {
+        (0..self.len()).map(move |index| self.get_with_origin(index))
+    }
+}
+
+// Networks added and removed since the ranges are built. The ones removed are also taken out of
+// the ones added, so an address is in the changed ranges if it's added, or it's in the ranges and
+// not removed.
+pub struct Changes<N: Net> {
+    added: IpRange<N>,
+    removed: IpRange<N>,
+}
+
+impl<N: Net> Default for Changes<N> {
+    fn default() -> Self {
+        Changes {
+            added: IpRange::new(),
+            removed: IpRange::new(),
+        }
+    }
+}
+
+impl<N: Net> Changes<N> {
+    pub fn add(&mut self, net: N) {
+        self.added.add(net).simplify();
+        self.removed.remove(net).simplify();
+    }
+
+    pub fn remove(&mut self, net: N) {
+        self.added.remove(net).simplify();
+        self.removed.add(net).simplify();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    // `Some` of the network added containing the address, or `Some(None)` if it's removed,
+    // `None` if it's not changed.
+    pub fn changed(&self, addr: &N::Addr) -> Option<Option<N>> {
+        if self.is_empty() {
+            None
+        } else if let Some(net) = self.added.supernet(addr) {
+            Some(Some(net))
+        } else if self.removed.contains(addr) {
+            Some(None)
+        } else {
+            None
+        }
+    }
+
+    // Rebuild the ranges and the entries with the changes, the entries removed in part are split
+    // into the networks left, with the same origins.
+    pub fn apply(self, ranges: &Ranges<N>, entries: &Ranges<N>) -> (Ranges<N>, Ranges<N>) {
+        let mut merged: IpRange<N> = ranges.iter().collect();
+        merged = merged.merge(&self.added).exclude(&self.removed);
+        let mut kept = Vec::with_capacity(entries.len());
+        for (net, origin) in entries.iter_with_origins() {
+            let mut left = IpRange::new();
+            left.add(net);
+            kept.extend(left.exclude(&self.removed).iter().map(|net| (net, origin)));
+        }
+        kept.extend(self.added.iter().map(|net| (net, OriginIndex::default())));
+        (
+            Ranges::new(merged.iter()),
+            Ranges::with_origins(kept.into_iter()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ranges;
+    use ipnet::Ipv4Net;
+
+    #[test]
+    fn find_supernet() {
+        let nets = ["10.0.0.0/8", "1.2.3.0/24", "192.168.1.0/24", "10.1.0.0/16"];
+        let ranges = Ranges::new(nets.iter().map(|net| net.parse::<Ipv4Net>().unwrap()));
+        let supernet = |ip: &str| ranges.supernet(&ip.parse().unwrap()).map(|n| n.to_string());
+        // `10.1.0.0/16` is contained by `10.0.0.0/8`, which is kept instead.
+        assert_eq!(supernet("10.1.2.3").as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(supernet("1.2.3.255").as_deref(), Some("1.2.3.0/24"));
+        assert_eq!(supernet("1.2.4.0"), None);
+        assert_eq!(supernet("0.0.0.1"), None);
+        assert_eq!(supernet("192.168.1.1").as_deref(), Some("192.168.1.0/24"));
+        assert_eq!(supernet("192.168.2.1"), None);
+        assert_eq!(
+            ranges.iter().map(|n| n.to_string()).collect::<Vec<_>>(),
+            vec!["1.2.3.0/24", "10.0.0.0/8", "192.168.1.0/24"]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Ordered routing rules, evaluated first-match-wins.
+
+use crate::{Domains, Ips};
+use ipnet::IpNet;
+use std::future::Future;
+use std::net::IpAddr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Direct,
+    /// Connect through the outbound of the index.
+    Proxy(usize),
+    Block(BlockMode),
+}
+
+/// How a blocked connection is rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockMode {
+    /// Respond `403 Forbidden` with the reason.
+    Forbidden,
+    /// Drop the connection without any response.
+    Reset,
+    /// Respond `200`, then close the connection.
+    Close,
+}
+
+pub struct Rule {
+    pub domains: Option<Domains>,
+    pub ips: Option<Ips>,
+    pub action: Action,
+}
+
+impl Rule {
+    pub fn domains(domains: Domains, action: Action) -> Rule {
+        Rule {
+            domains: Some(domains),
+            ips: None,
+            action,
+        }
+    }
+
+    pub fn ips(ips: Ips, action: Action) -> Rule {
+        Rule {
+            domains: None,
+            ips: Some(ips),
+            action,
+        }
+    }
+}
+
+pub struct Rules {
+    pub rules: Vec<Rule>,
+    /// Action taken when no rule matches.
+    pub default: Action,
+}
+
+/// A step of deciding the action, rules are referred by their indexes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// The host is matched by the domain entry of the rule, like `example.com` or
+    /// `keyword:google`, and where the entry is from, like `gfwlist.txt` or `list.txt:12`.
+    DomainMatched {
+        rule: usize,
+        entry: String,
+        origin: Option<String>,
+    },
+    /// The host is resolved, which happens at most once, when the first IP rule is reached.
+    Resolved(IpAddr),
+    /// The IP of the host is in the range of the rule, and where the range is from, like
+    /// `geo:cn`.
+    IpMatched {
+        rule: usize,
+        ipnet: IpNet,
+        origin: Option<String>,
+    },
+    NotMatched { rule: usize },
+    /// The rule only has domains, which are not checked since the host is an IP literal.
+    Skipped { rule: usize },
+    /// No rule matches, so the default action is taken.
+    Default,
+}
+
+/// How the action of a host is decided, see [`Rules::explain`].
+pub struct Explanation<E> {
+    pub steps: Vec<Step>,
+    /// The error of resolving the host if it fails, which ends the steps.
+    pub action: Result<Action, E>,
+}
+
+impl Rules {
+    /// Find the action of the first rule matching `host`, which is either a domain or an IP.
+    ///
+    /// The IP of a domain is only needed by the IP rules, so `resolve` is called lazily, at most
+    /// once, when the first IP rule is reached.
+    pub async fn action<F, Fut, E>(&self, host: &str, resolve: F) -> Result<Action, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<IpAddr, E>>,
+    {
+        self.decide(host, resolve, None).await
+    }
+
+    /// Decide the action the same as [`Rules::action`], with the steps taken.
+    pub async fn explain<F, Fut, E>(&self, host: &str, resolve: F) -> Explanation<E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<IpAddr, E>>,
+    {
+        let mut steps = Vec::new();
+        let action = self.decide(host, resolve, Some(&mut steps)).await;
+        Explanation { steps, action }
+    }
+
+    // The steps are only recorded when explaining.
+    async fn decide<F, Fut, E>(
+        &self,
+        host: &str,
+        resolve: F,
+        mut steps: Option<&mut Vec<Step>>,
+    ) -> Result<Action, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<IpAddr, E>>,
+    {
+        let literal = host.parse::<IpAddr>().ok();
+        let mut ip = literal;
+        let mut resolve = Some(resolve);
+        for (index, rule) in self.rules.iter().enumerate() {
+            if literal.is_none() {
+                if let Some(domains) = &rule.domains {
+                    if domains.contain_host(host.as_bytes()) {
+                        if let Some(steps) = steps.as_deref_mut() {
+                            let matched = domains
+                                .matched(host.as_bytes())
+                                .expect("the host is contained");
+                            steps.push(Step::DomainMatched {
+                                rule: index,
+                                entry: matched.entry,
+                                origin: matched.origin.map(|origin| origin.to_string()),
+                            });
+                        }
+                        return Ok(rule.action);
+                    }
+                }
+            }
+            if let Some(ips) = &rule.ips {
+                let ip = match ip {
+                    Some(ip) => ip,
+                    None => {
+                        let resolve = resolve.take().expect("resolve is called only once");
+                        let resolved = *ip.insert(resolve().await?);
+                        if let Some(steps) = steps.as_deref_mut() {
+                            steps.push(Step::Resolved(resolved));
+                        }
+                        resolved
+                    }
+                };
+                if ips.contain_ip(ip) {
+                    if let Some(steps) = steps.as_deref_mut() {
+                        let matched = ips.matched(ip).expect("the ip is contained");
+                        steps.push(Step::IpMatched {
+                            rule: index,
+                            ipnet: matched.entry,
+                            origin: matched.origin.map(|origin| origin.to_string()),
+                        });
+                    }
+                    return Ok(rule.action);
+                }
+            }
+            if let Some(steps) = steps.as_deref_mut() {
+                if literal.is_some() && rule.ips.is_none() {
+                    steps.push(Step::Skipped { rule: index });
+                } else {
+                    steps.push(Step::NotMatched { rule: index });
+                }
+            }
+        }
+        if let Some(steps) = steps {
+            steps.push(Step::Default);
+        }
+        Ok(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, BlockMode, Rule, Rules, Step};
+    use crate::{Domains, Ips};
+    use std::net::IpAddr;
+
+    fn rules() -> Rules {
+        Rules {
+            rules: vec![
+                Rule::domains(
+                    Domains::from_strs(["ads.example.com"].iter().copied()).unwrap(),
+                    Action::Block(BlockMode::Forbidden),
+                ),
+                Rule::domains(
+                    Domains::from_strs(["example.com"].iter().copied()).unwrap(),
+                    Action::Direct,
+                ),
+                Rule::ips(
+                    Ips::from_ipnets(["10.0.0.0/8".parse().unwrap()].iter().copied()).unwrap(),
+                    Action::Direct,
+                ),
+                Rule::domains(
+                    Domains::from_strs(["tracker.net"].iter().copied()).unwrap(),
+                    Action::Block(BlockMode::Forbidden),
+                ),
+            ],
+            default: Action::Proxy(0),
+        }
+    }
+
+    async fn action(rules: &Rules, host: &str, resolved: &str) -> (Action, bool) {
+        let mut called = false;
+        let action = rules
+            .action(host, || {
+                called = true;
+                async move { Ok::<IpAddr, ()>(resolved.parse().unwrap()) }
+            })
+            .await
+            .unwrap();
+        (action, called)
+    }
+
+    #[tokio::test]
+    async fn first_match_wins() {
+        let rules = rules();
+        let block = action(&rules, "x.ads.example.com", "1.1.1.1").await;
+        assert_eq!(block, (Action::Block(BlockMode::Forbidden), false));
+        let direct = action(&rules, "www.example.com", "1.1.1.1").await;
+        assert_eq!(direct, (Action::Direct, false));
+    }
+
+    #[tokio::test]
+    async fn resolve_for_ip_rules() {
+        let rules = rules();
+        let direct = action(&rules, "intranet.local", "10.1.2.3").await;
+        assert_eq!(direct, (Action::Direct, true));
+        let default = action(&rules, "google.com", "8.8.8.8").await;
+        assert_eq!(default, (Action::Proxy(0), true));
+        let literal = action(&rules, "10.0.0.1", "8.8.8.8").await;
+        assert_eq!(literal, (Action::Direct, false));
+        let after_ips = action(&rules, "tracker.net", "8.8.8.8").await;
+        assert_eq!(after_ips, (Action::Block(BlockMode::Forbidden), true));
+    }
+
+    #[tokio::test]
+    async fn explain_steps() {
+        let rules = rules();
+        let explanation = rules
+            .explain("www.example.com", || async { Err::<IpAddr, ()>(()) })
+            .await;
+        assert_eq!(explanation.action, Ok(Action::Direct));
+        assert_eq!(
+            explanation.steps,
+            vec![
+                Step::NotMatched { rule: 0 },
+                Step::DomainMatched {
+                    rule: 1,
+                    entry: "example.com".to_string(),
+                    origin: None,
+                },
+            ]
+        );
+
+        let explanation = rules
+            .explain("intranet.local", || async { Ok::<IpAddr, ()>([10, 1, 2, 3].into()) })
+            .await;
+        assert_eq!(explanation.action, Ok(Action::Direct));
+        assert_eq!(
+            explanation.steps[2..],
+            [
+                Step::Resolved([10, 1, 2, 3].into()),
+                Step::IpMatched {
+                    rule: 2,
+                    ipnet: "10.0.0.0/8".parse().unwrap(),
+                    origin: None,
+                },
+            ]
+        );
+
+        let literal = rules
+            .explain("10.0.0.1", || async { Err::<IpAddr, ()>(()) })
+            .await;
+        assert_eq!(literal.action, Ok(Action::Direct));
+        assert_eq!(
+            literal.steps,
+            vec![
+                Step::Skipped { rule: 0 },
+                Step::Skipped { rule: 1 },
+                Step::IpMatched {
+                    rule: 2,
+                    ipnet: "10.0.0.0/8".parse().unwrap(),
+                    origin: None,
+                },
+            ]
+        );
+
+        let failed = rules
+            .explain("unknown.net", || async { Err::<IpAddr, ()>(()) })
+            .await;
+        assert_eq!(failed.action, Err(()));
+    }
+}
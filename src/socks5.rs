@@ -0,0 +1,162 @@
+//! Server side of the SOCKS5 protocol (RFC 1928).
+//!
+//! Only the `CONNECT` command without authentication is supported.
+
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reply {
+    Succeeded = 0,
+    GeneralFailure = 1,
+    ConnectionNotAllowed = 2,
+    NetworkUnreachable = 3,
+    HostUnreachable = 4,
+    ConnectionRefused = 5,
+    CommandNotSupported = 7,
+    AddressTypeNotSupported = 8,
+}
+
+/// Destination of a `CONNECT` request.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Target {
+    /// Domain or IP, without brackets for IPv6.
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Negotiate with the client and read its request.
+///
+/// The request is replied with an error if it's not supported, otherwise it should be replied
+/// by [`reply`] after the connection to the target is established.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> io::Result<Target> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        return Err(invalid_data("unsupported socks version"));
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTHENTICATION) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        return Err(invalid_data("no acceptable authentication method"));
+    }
+    stream.write_all(&[VERSION, NO_AUTHENTICATION]).await?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    if request[0] != VERSION {
+        return Err(invalid_data("unsupported socks version"));
+    }
+    if request[1] != CONNECT {
+        reply(stream, Reply::CommandNotSupported).await?;
+        return Err(invalid_data("unsupported socks command"));
+    }
+    let host = match request[3] {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await?;
+            let mut domain = vec![0u8; len as usize];
+            stream.read_exact(&mut domain).await?;
+            String::from_utf8(domain).map_err(|_| invalid_data("illegal domain"))?
+        }
+        _ => {
+            reply(stream, Reply::AddressTypeNotSupported).await?;
+            return Err(invalid_data("unsupported address type"));
+        }
+    };
+    let port = stream.read_u16().await?;
+    Ok(Target { host, port })
+}
+
+/// Reply the request, the bound address is always reported as `0.0.0.0:0`.
+pub async fn reply<S: AsyncWrite + Unpin>(stream: &mut S, reply: Reply) -> io::Result<()> {
+    stream
+        .write_all(&[VERSION, reply as u8, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept, reply, Reply, Target};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn accept_connect() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let mut request = vec![5, 1, 0, 5, 1, 0, 3, 11];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&443u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        let target = accept(&mut server).await.unwrap();
+        assert_eq!(
+            target,
+            Target {
+                host: "example.com".to_string(),
+                port: 443
+            }
+        );
+        reply(&mut server, Reply::Succeeded).await.unwrap();
+
+        let mut response = [0u8; 12];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [5, 0, 5, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn reject_udp_associate() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client
+            .write_all(&[5, 1, 0, 5, 3, 0, 1, 127, 0, 0, 1, 0, 53])
+            .await
+            .unwrap();
+
+        assert!(accept(&mut server).await.is_err());
+        let mut response = [0u8; 4];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [5, 0, 5, Reply::CommandNotSupported as u8]);
+    }
+
+    #[test]
+    fn display_target() {
+        let ipv6 = Target {
+            host: "::1".to_string(),
+            port: 80,
+        };
+        assert_eq!(ipv6.to_string(), "[::1]:80");
+    }
+}
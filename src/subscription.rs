@@ -0,0 +1,143 @@
+// Rule sources defined by URL, which are downloaded into the cache directory periodically. The
+// config only reads the cache, so a source is still available when the download fails.
+
+use crate::{Outbound, Socks5Connector};
+use anyhow::{anyhow, Context};
+use hyper::client::connect::Connection;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Client, Uri};
+use hyper_rustls::HttpsConnectorBuilder;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Subscription {
+    pub name: String,
+    pub url: Uri,
+    pub interval: Duration,
+    // Index of the outbound to download through, directly if `None`.
+    pub outbound: Option<usize>,
+    pub cache: PathBuf,
+}
+
+impl Subscription {
+    // Whether the cache is missing or older than the interval.
+    pub fn is_due(&self) -> bool {
+        match fs::metadata(&self.cache).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified
+                .elapsed()
+                .map_or(true, |elapsed| elapsed >= self.interval),
+            Err(_) => true,
+        }
+    }
+
+    // Replace the cache by the downloaded content, the cache is kept if it fails.
+    pub async fn download(&self, outbounds: &[Arc<Outbound>]) -> anyhow::Result<()> {
+        let body = match self.outbound {
+            None => {
+                let mut connector = HttpConnector::new();
+                connector.enforce_http(false);
+                get(connector, &self.url).await?
+            }
+            Some(outbound) => {
+                let connector = Socks5Connector(outbounds[outbound].clone());
+                get(connector, &self.url).await?
+            }
+        };
+        if let Some(dir) = self.cache.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("unable to create `{}`", dir.display()))?;
+        }
+        // A partial file never replaces the cache.
+        let downloading = self.cache.with_extension("downloading");
+        tokio::fs::write(&downloading, &body).await?;
+        tokio::fs::rename(&downloading, &self.cache).await?;
+        Ok(())
+    }
+}
+
+async fn get<C>(connector: C, url: &Uri) -> anyhow::Result<hyper::body::Bytes>
+where
+    C: Service<Uri> + Clone + Send + Sync + 'static,
+    C::Response: Connection + AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C::Future: Send + Unpin + 'static,
+    C::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let connector = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(connector);
+    let client: Client<_> = Client::builder().build(connector);
+    let download = async {
+        let response = client.get(url.clone()).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("unexpected status {}", response.status()));
+        }
+        Ok(hyper::body::to_bytes(response.into_body()).await?)
+    };
+    tokio::time::timeout(DOWNLOAD_TIMEOUT, download)
+        .await
+        .map_err(|_| anyhow!("timed out"))?
+        .with_context(|| format!("unable to download `{}`", url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Subscription;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, Server, StatusCode};
+    use std::convert::Infallible;
+    use std::fs;
+    use std::time::Duration;
+
+    // Serve `/list.txt`, and 404 for the other paths.
+    fn serve() -> std::net::SocketAddr {
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req| async move {
+                let response = if req.uri().path() == "/list.txt" {
+                    Response::new(Body::from("example.com\n"))
+                } else {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                    response
+                };
+                Ok::<_, Infallible>(response)
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn download_to_cache() {
+        let addr = serve();
+        let dir = std::env::temp_dir().join(format!("h2sr-subscription-{}", std::process::id()));
+        let subscription = |path: &str| Subscription {
+            name: "list".to_string(),
+            url: format!("http://{}{}", addr, path).parse().unwrap(),
+            interval: Duration::from_secs(60),
+            outbound: None,
+            cache: dir.join("cache").join("list"),
+        };
+
+        let list = subscription("/list.txt");
+        assert!(list.is_due());
+        list.download(&[]).await.unwrap();
+        assert_eq!(fs::read_to_string(&list.cache).unwrap(), "example.com\n");
+        assert!(!list.is_due());
+
+        // The cache is kept if the download fails.
+        assert!(subscription("/missing.txt").download(&[]).await.is_err());
+        assert_eq!(fs::read_to_string(&list.cache).unwrap(), "example.com\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+// Radix trie of the reversed domains, in which a chain of single-child nodes is merged into one
+// node labeled by the bytes of the chain, so a domain list costs 12 bytes per branch and a byte per
+// distinct character. A node is matched by the flags of the domain ending at it, and the origin of
+// the domain is stored apart from the nodes, by the index of the node.
+
+use crate::db::{Buffer, Data, Reader};
+use crate::origin::{OriginIndex, ORIGIN_SIZE};
+use crate::{Error, Result};
+
+// Flags of the domain ending at a node.
+pub const SUFFIX: u8 = 1;
+pub const FULL: u8 = 2;
+
+// A reversed lowercase domain, with its flags and where it's from.
+pub type Entry = (Vec<u8>, u8, OriginIndex);
+
+#[derive(Clone, Copy, Default)]
+struct Node {
+    // Reversed bytes from the parent to the node, in `labels`, and the first of them, which is
+    // compared without looking up the labels.
+    label_start: u32,
+    label_len: u8,
+    first: u8,
+    flags: u8,
+    num_children: u8,
+    // The children are consecutive, sorted by the first byte of their labels.
+    first_child: u32,
+}
+
+// Size of an encoded node.
+const NODE_SIZE: usize = 12;
+
+impl Node {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.label_start.to_le_bytes());
+        bytes.extend_from_slice(&[self.label_len, self.first, self.flags, self.num_children]);
+        bytes.extend_from_slice(&self.first_child.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Node {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Node {
+            label_start: u32_at(0),
+            label_len: bytes[4],
+            first: bytes[5],
+            flags: bytes[6],
+            num_children: bytes[7],
+            first_child: u32_at(8),
+        }
+    }
+}
+
+pub struct Trie {
+    // Encoded nodes, the root is the first one, with an empty label.
+    nodes: Data,
+    labels: Data,
+    // Origins of the domains ending at the nodes, by the indexes of the nodes. Empty if all of
+    // them are unknown.
+    origins: Data,
+}
+
+impl Default for Trie {
+    fn default() -> Self {
+        let mut nodes = Vec::new();
+        Node::default().encode(&mut nodes);
+        Trie {
+            nodes: Data::Owned(nodes),
+            labels: Data::default(),
+            origins: Data::default(),
+        }
+    }
+}
+
+impl Trie {
+    // Build from the entries. The flags of the same domain are merged, and the domains covered by
+    // a suffix are left out of the trie, which are returned, so they can be added back once the
+    // suffix is removed.
+    pub fn build(mut entries: Vec<Entry>) -> Result<(Trie, Vec<Entry>)> {
+        entries.sort_unstable();
+        // The origin of the first one is kept, whose flags sort first, so it's the origin of the
+        // suffix if the domain is added both as a suffix and as a full domain.
+        entries.dedup_by(|next, kept| {
+            let same = next.0 == kept.0;
+            if same {
+                kept.1 |= next.1;
+            }
+            same
+        });
+
+        let mut nodes = vec![Node::default()];
+        let mut origins = vec![OriginIndex::default()];
+        let mut labels = Vec::new();
+        let mut covered_ranges = Vec::new();
+        // Nodes whose children are not created yet, with their entries and their depth, which
+        // is the length of the domain ending at the node.
+        let mut pending = vec![(0, 0..entries.len(), 0)];
+        while let Some((node, range, depth)) = pending.pop() {
+            let mut start = range.start;
+            if start < range.end && entries[start].0.len() == depth {
+                nodes[node].flags = entries[start].1;
+                origins[node] = entries[start].2;
+                start += 1;
+            }
+            let covered = nodes[node].flags & SUFFIX != 0;
+            let first_child = nodes.len();
+            while start < range.end {
+                let first = entries[start].0[depth];
+                let end = start
+                    + entries[start..range.end]
+                        .iter()
+                        .take_while(|(domain, _, _)| domain[depth] == first)
+                        .count();
+                if covered && first == b'.' {
+                    covered_ranges.push(start..end);
+                    start = end;
+                    continue;
+                }
+                // The entries are sorted, so the common prefix of the first and the last one is
+                // the one of all.
+                let (head, tail) = (&entries[start].0[depth..], &entries[end - 1].0[depth..]);
+                let len = head
+                    .iter()
+                    .zip(tail)
+                    .take_while(|(a, b)| a == b)
+                    .count()
+                    .min(u8::MAX as usize);
+                let child = Node {
+                    label_start: to_u32(labels.len())?,
+                    label_len: len as u8,
+                    first,
+                    ..Node::default()
+                };
+                labels.extend_from_slice(&head[..len]);
+                pending.push((nodes.len(), start..end, depth + len));
+                nodes.push(child);
+                origins.push(OriginIndex::default());
+                start = end;
+            }
+            let num_children = nodes.len() - first_child;
+            if num_children > 0 {
+                to_u32(nodes.len())?;
+                nodes[node].first_child = first_child as u32;
+                // Bytes of the domains are validated, far fewer than `u8::MAX` kinds.
+                nodes[node].num_children = num_children as u8;
+            }
+        }
+        let mut encoded = Vec::with_capacity(nodes.len() * NODE_SIZE);
+        for node in &nodes {
+            node.encode(&mut encoded);
+        }
+        labels.shrink_to_fit();
+        let mut encoded_origins = Vec::new();
+        if origins.iter().any(|origin| !origin.is_unknown()) {
+            encoded_origins.reserve_exact(origins.len() * ORIGIN_SIZE);
+            for origin in &origins {
+                origin.encode(&mut encoded_origins);
+            }
+        }
+        let trie = Trie {
+            nodes: Data::Owned(encoded),
+            labels: Data::Owned(labels),
+            origins: Data::Owned(encoded_origins),
+        };
+        let covered = covered_ranges
+            .into_iter()
+            .flat_map(|range| entries[range].iter().cloned())
+            .collect();
+        Ok((trie, covered))
+    }
+
+    pub fn write_to(&self, bytes: &mut Vec<u8>) {
+        crate::db::write_bytes(bytes, &self.nodes);
+        crate::db::write_bytes(bytes, &self.labels);
+        crate::db::write_bytes(bytes, &self.origins);
+    }
+
+    // Use the trie in the database in place, after checking the nodes are in bounds, and the
+    // children are after their parents, so `find` always ends.
+    pub fn read_from(reader: &mut Reader, buffer: &Buffer) -> Result<Trie> {
+        let trie = Trie {
+            nodes: Data::Shared(buffer.clone(), reader.bytes_range()?),
+            labels: Data::Shared(buffer.clone(), reader.bytes_range()?),
+            origins: Data::Shared(buffer.clone(), reader.bytes_range()?),
+        };
+        let len = trie.nodes.len() / NODE_SIZE;
+        if len == 0
+            || !trie.nodes.len().is_multiple_of(NODE_SIZE)
+            || !(trie.origins.is_empty() || trie.origins.len() == len * ORIGIN_SIZE)
+        {
+            return Err(Error::IllegalDatabase("illegal trie".to_string()));
+        }
+        for index in 0..len {
+            let node = trie.node(index);
+            let children = trie.children(&node);
+            let label = node.label_start as usize + node.label_len as usize;
+            if label > trie.labels.len()
+                || (index > 0 && node.label_len == 0)
+                || (!children.is_empty() && (children.start <= index || children.end > len))
+            {
+                return Err(Error::IllegalDatabase("illegal trie".to_string()));
+            }
+        }
+        Ok(trie)
+    }
+
+    fn node(&self, index: usize) -> Node {
+        Node::decode(&self.nodes[index * NODE_SIZE..(index + 1) * NODE_SIZE])
+    }
+
+    // The entries of the domains, in the form of `build`, with the indexes of their nodes.
+    pub fn entries(&self) -> Vec<(usize, Entry)> {
+        let mut entries = Vec::new();
+        let mut pending = vec![(0, Vec::new())];
+        while let Some((index, mut domain)) = pending.pop() {
+            let node = self.node(index);
+            domain.extend_from_slice(self.label(&node));
+            if node.flags != 0 {
+                entries.push((index, (domain.clone(), node.flags, self.origin(index))));
+            }
+            for child in self.children(&node) {
+                pending.push((child, domain.clone()));
+            }
+        }
+        entries
+    }
+
+    // The length of the suffix of the host matched, whether it's matched as a full domain, and
+    // the index of the node the matched domain ends at. The host is matched case-insensitively.
+    pub fn find_node(&self, host: &[u8]) -> Option<(usize, bool, usize)> {
+        self.find_node_where(host, |_, _| true)
+    }
+
+    // `find_node` of the domains accepted by the node index and whether it's matched as a full
+    // domain, so the longer ones are matched if the shorter ones are not accepted.
+    pub fn find_node_where(
+        &self,
+        host: &[u8],
+        accept: impl Fn(usize, bool) -> bool,
+    ) -> Option<(usize, bool, usize)> {
+        let mut matched = 0;
+        let mut index = 0;
+        let mut node = self.node(0);
+        loop {
+            let next = host.len() - matched;
+            if node.flags & SUFFIX != 0
+                && (next == 0 || host[next - 1] == b'.')
+                && accept(index, false)
+            {
+                return Some((matched, false, index));
+            }
+            if next == 0 {
+                return if node.flags & FULL != 0 && accept(index, true) {
+                    Some((matched, true, index))
+                } else {
+                    None
+                };
+            }
+            let b = host[next - 1].to_ascii_lowercase();
+            let (child, child_node) = self
+                .children(&node)
+                .map(|child| (child, self.node(child)))
+                .find(|(_, child)| child.first == b)?;
+            index = child;
+            node = child_node;
+            let label = self.label(&node);
+            if label.len() > next
+                || !label
+                    .iter()
+                    .zip(host[..next].iter().rev())
+                    .all(|(a, b)| *a == b.to_ascii_lowercase())
+            {
+                return None;
+            }
+            matched += label.len();
+        }
+    }
+
+    // The index of the node the reversed lowercase domain ends at, if it's in the trie.
+    pub fn get(&self, domain: &[u8]) -> Option<usize> {
+        let mut matched = 0;
+        let mut index = 0;
+        while matched < domain.len() {
+            let node = self.node(index);
+            index = self
+                .children(&node)
+                .find(|child| self.node(*child).first == domain[matched])?;
+            let label = self.label(&self.node(index));
+            if !domain[matched..].starts_with(label) {
+                return None;
+            }
+            matched += label.len();
+        }
+        Some(index).filter(|index| self.flags(*index) != 0)
+    }
+
+    pub fn flags(&self, index: usize) -> u8 {
+        self.node(index).flags
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == NODE_SIZE && self.node(0).flags == 0
+    }
+
+    // The number of domains.
+    pub fn len(&self) -> usize {
+        (0..self.nodes.len() / NODE_SIZE)
+            .filter(|index| self.node(*index).flags != 0)
+            .count()
+    }
+
+    // Bytes of the nodes, the labels and the origins, including the ones in the database.
+    pub fn size(&self) -> usize {
+        self.nodes.len() + self.labels.len() + self.origins.len()
+    }
+
+    pub fn origin(&self, index: usize) -> OriginIndex {
+        self.origins
+            .get(index * ORIGIN_SIZE..(index + 1) * ORIGIN_SIZE)
+            .map_or_else(OriginIndex::default, OriginIndex::decode)
+    }
+
+    fn label(&self, node: &Node) -> &[u8] {
+        let start = node.label_start as usize;
+        &self.labels[start..start + node.label_len as usize]
+    }
+
+    fn children(&self, node: &Node) -> std::ops::Range<usize> {
+        let first = node.first_child as usize;
+        first..first + node.num_children as usize
+    }
+}
+
+// `Trie::find_node` of a trie containing the domain only, without the node.
+pub fn match_one(domain: &[u8], flags: u8, host: &[u8]) -> Option<(usize, bool)> {
+    let next = host.len().checked_sub(domain.len())?;
+    if !domain
+        .iter()
+        .zip(host.iter().rev())
+        .all(|(a, b)| *a == b.to_ascii_lowercase())
+    {
+        return None;
+    }
+    if flags & SUFFIX != 0 && (next == 0 || host[next - 1] == b'.') {
+        Some((domain.len(), false))
+    } else if flags & FULL != 0 && next == 0 {
+        Some((domain.len(), true))
+    } else {
+        None
+    }
+}
+
+fn to_u32(index: usize) -> Result<u32> {
+    if index > u32::MAX as usize {
+        return Err(Error::TooManyDomains);
+    }
+    Ok(index as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Trie, FULL, SUFFIX};
+    use crate::origin::OriginIndex;
+
+    fn reversed(domain: &str) -> Vec<u8> {
+        domain.bytes().rev().collect()
+    }
+
+    #[test]
+    fn compress_and_find() {
+        let entries = [
+            ("example.com", SUFFIX),
+            ("api.example.com", FULL),
+            ("example.org", FULL),
+            ("sub.example.org", SUFFIX),
+            ("example.org", SUFFIX),
+            ("xample.com", FULL),
+        ];
+        let (trie, covered) = Trie::build(
+            entries
+                .iter()
+                .map(|(domain, flag)| (reversed(domain), *flag, OriginIndex::default()))
+                .collect(),
+        )
+        .unwrap();
+
+        let find = |host: &[u8]| trie.find_node(host).map(|(len, full, _)| (len, full));
+        // `api.example.com` is covered by `example.com`, and the flags of `example.org` are
+        // merged.
+        assert_eq!(trie.len(), 3);
+        let mut covered: Vec<_> = covered.into_iter().map(|(domain, _, _)| domain).collect();
+        covered.sort();
+        assert_eq!(
+            covered,
+            vec![reversed("sub.example.org"), reversed("api.example.com")]
+        );
+        assert_eq!(find(b"www.Example.COM"), Some((11, false)));
+        assert_eq!(find(b"xample.com"), Some((10, true)));
+        assert_eq!(find(b"axample.com"), None);
+        assert_eq!(find(b"example.org"), Some((11, false)));
+        assert_eq!(find(b"a.sub.example.org"), Some((11, false)));
+        assert_eq!(find(b"com"), None);
+        assert_eq!(find(b""), None);
+
+        assert_eq!(
+            trie.get(&reversed("example.org")).map(|i| trie.flags(i)),
+            Some(FULL | SUFFIX)
+        );
+        assert_eq!(trie.get(&reversed("api.example.com")), None);
+        assert_eq!(trie.get(&reversed("example")), None);
+        // The suffix not accepted is skipped for the full domain.
+        let find_full = |host: &[u8]| {
+            trie.find_node_where(host, |_, full| full)
+                .map(|(len, full, _)| (len, full))
+        };
+        assert_eq!(find_full(b"example.org"), Some((11, true)));
+        assert_eq!(find_full(b"www.example.org"), None);
+
+        let sorted = |trie: &Trie| {
+            let mut entries: Vec<_> = trie.entries().into_iter().map(|(_, entry)| entry).collect();
+            entries.sort();
+            entries
+        };
+        let (rebuilt, _) = Trie::build(sorted(&trie)).unwrap();
+        assert_eq!(sorted(&rebuilt), sorted(&trie));
+        assert!(Trie::build(Vec::new()).unwrap().0.is_empty());
+    }
+}